@@ -1,13 +1,10 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use imagemusic::image::{Image, Payload, Pixel};
 use rand::Rng;
 
-pub fn criterion_benchmark(c: &mut Criterion) {
+fn random_image(dimensions: (u32, u32)) -> Image {
     let mut rng = rand::thread_rng();
-    let dimensions = (500, 500);
-    let data: Vec<u8> = (0..1000).map(|_| rng.gen()).collect();
-    let payload = Payload::new(&data);
-    let origin_image = Image::new(
+    Image::new(
         dimensions,
         std::iter::from_fn(|| {
             Some(Pixel {
@@ -19,11 +16,21 @@ pub fn criterion_benchmark(c: &mut Criterion) {
         })
         .take(dimensions.0 as usize * dimensions.1 as usize)
         .collect::<Vec<Pixel>>(),
-    );
+    )
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let dimensions = (500, 500);
+    let data: Vec<u8> = (0..1000).map(|_| rng.gen()).collect();
+    let payload = Payload::new(&data).expect("Could not create payload");
+    let origin_image = random_image(dimensions);
     c.bench_function("image 500x500 1000 rand", move |b| {
         b.iter(|| {
             let mut image = origin_image.clone();
-            image.bake_payload(&payload);
+            image
+                .bake_payload(&payload)
+                .expect("Could not bake payload");
             let read_data = image
                 .read_payload()
                 .expect("Could not read payload")
@@ -34,25 +41,15 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     });
 
     let data: Vec<u8> = (0..500).map(|_| rng.gen()).collect();
-    let payload = Payload::new(&data);
-    let origin_image = Image::new(
-        dimensions,
-        std::iter::from_fn(|| {
-            Some(Pixel {
-                r: rng.gen(),
-                g: rng.gen(),
-                b: rng.gen(),
-                a: rng.gen(),
-            })
-        })
-        .take(dimensions.0 as usize * dimensions.1 as usize)
-        .collect::<Vec<Pixel>>(),
-    );
+    let payload = Payload::new(&data).expect("Could not create payload");
+    let origin_image = random_image(dimensions);
 
     c.bench_function("image 500x500 500 rand", move |b| {
         b.iter(|| {
             let mut image = origin_image.clone();
-            image.bake_payload(&payload);
+            image
+                .bake_payload(&payload)
+                .expect("Could not bake payload");
             let read_data = image
                 .read_payload()
                 .expect("Could not read payload")
@@ -63,5 +60,48 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// Poster-sized images, where the time goes into walking every pixel rather than finding the
+/// payload.  Run with `--features parallel` to compare.
+pub fn poster_benchmark(c: &mut Criterion) {
+    let mut rng = rand::thread_rng();
+    let data: Vec<u8> = (0..4000).map(|_| rng.gen()).collect();
+    let payload = Payload::new(&data).expect("Could not create payload");
+
+    let mut group = c.benchmark_group("poster");
+    group.sample_size(10);
+    for &dimensions in &[(2000, 2000), (3840, 2160), (4000, 4000)] {
+        let origin_image = random_image(dimensions);
+        let mut baked = origin_image.clone();
+        baked
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
+        let name = format!("{}x{}", dimensions.0, dimensions.1);
+
+        group.bench_with_input(
+            BenchmarkId::new("bake", &name),
+            &origin_image,
+            |b, image| {
+                b.iter(|| {
+                    let mut image = image.clone();
+                    image
+                        .bake_payload(&payload)
+                        .expect("Could not bake payload");
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("read", &name), &baked, |b, image| {
+            b.iter(|| {
+                let read_data = image
+                    .read_payload()
+                    .expect("Could not read payload")
+                    .data()
+                    .expect("Could not read data");
+                assert_eq!(data, read_data);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark, poster_benchmark);
 criterion_main!(benches);
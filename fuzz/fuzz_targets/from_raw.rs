@@ -0,0 +1,22 @@
+//! Decode arbitrary grids of superpixels, as if they had been read out of an image.
+#![no_main]
+use imagemusic::image::{Payload, Superpixel};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The largest square grid the data fills.
+    let width = (data.len() as f64).sqrt() as u32;
+    let superpixels: Vec<Superpixel> = data[..(width * width) as usize]
+        .iter()
+        .map(|&cell| match cell & 3 {
+            0 => Superpixel::Black,
+            1 => Superpixel::White,
+            _ => Superpixel::Value((cell >> 2) as u16),
+        })
+        .collect();
+    if let Ok(payload) = Payload::from_raw(width, superpixels) {
+        let _ = payload.header();
+        let _ = payload.data();
+        let _ = imagemusic::unpack_payload(&payload, Some("passphrase"));
+    }
+});
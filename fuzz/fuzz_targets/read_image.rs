@@ -0,0 +1,11 @@
+//! Read songs out of arbitrary RGBA images, whose data needn't even match their dimensions.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let (dimensions, pixels) = data.split_at(2);
+    let _ = imagemusic::song_from_rgba(dimensions[0] as u32, dimensions[1] as u32, pixels, None);
+});
@@ -0,0 +1,36 @@
+//! Unpack arbitrary data from intact payloads, which has to get past every flag, decryption,
+//! signature, decompression, and deserialization on its own.
+#![no_main]
+use imagemusic::image::{Codec, Compression, Flags, Frame, Header, Payload};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let (&settings, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let mut header = Header {
+        compression: if settings & 1 == 0 {
+            Compression::None
+        } else {
+            Compression::Gzip
+        },
+        codec: Codec::Bincode,
+        ..Header::default()
+    };
+    if settings & 2 != 0 {
+        header.flags.insert(Flags::ENCRYPTED);
+    }
+    if settings & 4 != 0 {
+        header.flags.insert(Flags::SIGNED);
+    }
+    if settings & 8 != 0 {
+        header.frame = Some(Frame {
+            index: (settings >> 4 & 3) as u16,
+            count: (settings >> 6) as u16,
+        });
+    }
+    if let Ok(payload) = Payload::with_header(data, header) {
+        let _ = imagemusic::unpack_payload(&payload, Some("passphrase"));
+    }
+});
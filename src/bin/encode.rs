@@ -1,60 +1,360 @@
-use image::{DynamicImage, RgbaImage};
-use imagemusic::image::{Image, Payload, Pixel};
-use imagemusic::Song;
+use image::gif::Encoder as GifEncoder;
+use image::png::PNGEncoder;
+use image::{ColorType, Delay, DynamicImage, Frame, RgbaImage};
+use imagemusic::image::{
+    Baking, Codec, Compression, Density, Encoding, Flags, Header, Image, Layout, Payload, Pixel,
+    Placement, Rounding,
+};
+use imagemusic::signing::SecretKey;
+use imagemusic::{encryption, Song};
 use std::env;
-use std::fs;
-use std::io::Read;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+/// How long each frame of an animation shows for.
+const FRAME_DELAY_MS: u32 = 1000;
+
+/// Superpixels smaller than this many pixels on a side are warned about, unless a minimum is given.
+const WARN_SUPERPIXEL: u32 = 4;
+
+/// Append a PNG chunk, with its length and CRC.
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    png.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+/// Save frames of the same size as an animated PNG.  The image crate can only read these, so
+/// each frame is written as a PNG of its own and its image data moved into frame chunks.
+fn save_apng(path: &str, frames: &[RgbaImage]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut apng = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut sequence = 0u32;
+    for (index, frame) in frames.iter().enumerate() {
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png).encode(frame, frame.width(), frame.height(), ColorType::Rgba8)?;
+
+        // Split the PNG into its chunks, past the signature.
+        let mut ihdr = &[][..];
+        let mut idat = Vec::new();
+        let mut rest = &png[8..];
+        while rest.len() >= 12 {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            match kind {
+                b"IHDR" => ihdr = data,
+                b"IDAT" => idat.extend_from_slice(data),
+                _ => (),
+            }
+            rest = &rest[12 + len..];
+        }
+
+        if index == 0 {
+            push_chunk(&mut apng, b"IHDR", ihdr);
+            let mut actl = (frames.len() as u32).to_be_bytes().to_vec();
+            // Loop forever.
+            actl.extend_from_slice(&0u32.to_be_bytes());
+            push_chunk(&mut apng, b"acTL", &actl);
+        }
+
+        let mut fctl = sequence.to_be_bytes().to_vec();
+        sequence += 1;
+        fctl.extend_from_slice(&frame.width().to_be_bytes());
+        fctl.extend_from_slice(&frame.height().to_be_bytes());
+        // The frame covers the whole image from its corner.
+        fctl.extend_from_slice(&[0; 8]);
+        fctl.extend_from_slice(&(FRAME_DELAY_MS as u16).to_be_bytes());
+        fctl.extend_from_slice(&1000u16.to_be_bytes());
+        // Neither dispose of nor blend with the previous frame.
+        fctl.extend_from_slice(&[0, 0]);
+        push_chunk(&mut apng, b"fcTL", &fctl);
+
+        if index == 0 {
+            push_chunk(&mut apng, b"IDAT", &idat);
+        } else {
+            let mut fdat = sequence.to_be_bytes().to_vec();
+            sequence += 1;
+            fdat.extend(idat);
+            push_chunk(&mut apng, b"fdAT", &fdat);
+        }
+    }
+    push_chunk(&mut apng, b"IEND", &[]);
+    fs::write(path, apng)?;
+    Ok(())
+}
 
 /// Compress into image(brotli(bincode(song)))
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().skip(1).collect();
     if args.len() < 3 {
-        panic!("imagemusic {input song} {input image} {output image}");
+        panic!(
+            "imagemusic {input song} {input image} {output image} [--finders] [--jpeg] [--density {robust|standard|dense|densest|grayscale}] [--place {x,y,width,height}] [--quiet-zone {pixels}] [--tiles {columns}x{rows}] [--rounding {center|nearest|diffused}] [--passphrase {passphrase}] [--ask-passphrase] [--sign {key file}] [--new-key {key file}] [--frames {count}] [--min-superpixel {pixels}] [--interleave] [--square] [--metrics]"
+        );
     }
     let songpath = &args[0];
     let inputimagepath = &args[1];
     let outputimagepath = &args[2];
-    let song_toml = fs::read_to_string(songpath)?;
-    let song: Song = toml::from_str(&song_toml)?;
-    let bincode = bincode::serialize(&song)?;
-    let mut compressed = Vec::new();
-    {
-        let mut compressor =
-            flate2::read::GzEncoder::new(bincode.as_slice(), flate2::Compression::best());
-        compressor.read_to_end(&mut compressed)?;
-    }
-    dbg!(compressed.len());
-
-    let payload = Payload::new(&compressed);
 
+    let mut layout = Layout::Target;
+    let mut density = Density::Standard;
+    let mut baking = Baking::default();
+    let mut passphrase = None;
+    let mut key = None;
+    let mut frames = None;
+    let mut min_superpixel = None;
+    let mut interleave = false;
+    let mut square = false;
+    let mut metrics = false;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            // Finder patterns let the image be read back from a photo of a print.
+            "--finders" => layout = Layout::Finders,
+            // Flat superpixels lined up with JPEG's blocks survive being recompressed.
+            "--jpeg" => {
+                baking = Baking {
+                    placement: baking.placement,
+                    quiet_zone: baking.quiet_zone,
+                    tiles: baking.tiles,
+                    rounding: baking.rounding,
+                    ..Baking::jpeg()
+                }
+            }
+            // A rectangle in pixels, or in fractions of the image when any part has a point.
+            "--place" => {
+                let place = options
+                    .next()
+                    .unwrap_or_else(|| panic!("--place needs a rectangle"));
+                let parts: Vec<&str> = place.split(',').collect();
+                if parts.len() != 4 {
+                    panic!("--place needs four parts, not {}", place);
+                }
+                baking.placement = if place.contains('.') {
+                    let parts: Vec<f32> = parts
+                        .iter()
+                        .map(|part| part.parse())
+                        .collect::<Result<_, _>>()?;
+                    Placement::Fraction {
+                        x: parts[0],
+                        y: parts[1],
+                        width: parts[2],
+                        height: parts[3],
+                    }
+                } else {
+                    let parts: Vec<u32> = parts
+                        .iter()
+                        .map(|part| part.parse())
+                        .collect::<Result<_, _>>()?;
+                    Placement::Pixels {
+                        x: parts[0],
+                        y: parts[1],
+                        width: parts[2],
+                        height: parts[3],
+                    }
+                };
+            }
+            "--quiet-zone" => {
+                baking.quiet_zone = options
+                    .next()
+                    .unwrap_or_else(|| panic!("--quiet-zone needs a width"))
+                    .parse()?
+            }
+            "--rounding" => {
+                baking.rounding = match options.next().map(String::as_str) {
+                    Some("center") => Rounding::Center,
+                    Some("nearest") => Rounding::Nearest,
+                    Some("diffused") => Rounding::Diffused,
+                    rounding => panic!("Unknown rounding {:?}", rounding),
+                }
+            }
+            "--density" => {
+                density = match options.next().map(String::as_str) {
+                    Some("robust") => Density::Robust,
+                    Some("standard") => Density::Standard,
+                    Some("dense") => Density::Dense,
+                    Some("densest") => Density::Densest,
+                    Some("grayscale") => Density::Grayscale,
+                    density => panic!("Unknown density {:?}", density),
+                }
+            }
+            // Copies of the payload, so that the song survives cropping.
+            "--tiles" => {
+                let tiles = options
+                    .next()
+                    .unwrap_or_else(|| panic!("--tiles needs a tiling"));
+                let (columns, rows) = tiles
+                    .split_once('x')
+                    .unwrap_or_else(|| panic!("--tiles needs columns and rows, not {}", tiles));
+                baking.tiles = (columns.parse()?, rows.parse()?);
+            }
+            // Only readers who know the passphrase can get the song back out.
+            "--passphrase" => {
+                passphrase = Some(
+                    options
+                        .next()
+                        .unwrap_or_else(|| panic!("--passphrase needs a passphrase"))
+                        .clone(),
+                )
+            }
+            // Kept out of the shell's history.
+            "--ask-passphrase" => passphrase = Some(encryption::ask_passphrase()?),
+            // Proves who baked the image to anyone who knows the public key.
+            "--sign" => {
+                let keypath = options
+                    .next()
+                    .unwrap_or_else(|| panic!("--sign needs a key file"));
+                key = Some(SecretKey::from_hex(&fs::read_to_string(keypath)?)?);
+            }
+            // Never overwrites a key that already exists.
+            "--new-key" => {
+                let keypath = options
+                    .next()
+                    .unwrap_or_else(|| panic!("--new-key needs a key file"));
+                let new_key = SecretKey::generate()?;
+                let mut file_options = OpenOptions::new();
+                file_options.write(true).create_new(true);
+                // Only readable by its owner, since anyone who can read it can sign as them.
+                #[cfg(unix)]
+                file_options.mode(0o600);
+                let mut file = file_options.open(keypath)?;
+                writeln!(file, "{}", new_key.to_hex())?;
+                key = Some(new_key);
+            }
+            // Spread the song over the frames of an animated GIF or PNG, for songs too long to
+            // fit in one.
+            "--frames" => {
+                frames = Some(
+                    options
+                        .next()
+                        .unwrap_or_else(|| panic!("--frames needs a count"))
+                        .parse()?,
+                )
+            }
+            // Refuse to bake superpixels smaller than this, rather than just warning.
+            "--min-superpixel" => {
+                min_superpixel = Some(
+                    options
+                        .next()
+                        .unwrap_or_else(|| panic!("--min-superpixel needs a size"))
+                        .parse()?,
+                )
+            }
+            // Scatter the data, so that a smudge or sticker leaves repairable errors all over
+            // instead of one hole.
+            "--interleave" => interleave = true,
+            // Keep the grid square instead of following the image's shape, for readers from
+            // before grids could be rectangular.
+            "--square" => square = true,
+            // Report how far baking moved each frame from the original, which takes a pass over
+            // every pixel of both.
+            "--metrics" => metrics = true,
+            option => panic!("Unknown option {}", option),
+        }
+    }
     let image = image::open(inputimagepath)?;
     let image = image.into_rgba();
     let dimensions = image.dimensions();
 
-    let pixels: Vec<_> = image
-        .pixels()
-        .map(|pixel| Pixel {
-            r: pixel[0],
-            g: pixel[1],
-            b: pixel[2],
-            a: pixel[3],
-        })
-        .collect();
+    let song_toml = fs::read_to_string(songpath)?;
+    let song: Song = toml::from_str(&song_toml)?;
+    let mut compressed = imagemusic::compress_song(&song)?;
+    eprintln!("Song is {} bytes compressed", compressed.len());
+
+    let mut header = Header {
+        compression: Compression::Gzip,
+        codec: Codec::Bincode,
+        ..Header::default()
+    };
+    if interleave {
+        header.flags.insert(Flags::INTERLEAVED);
+    }
+    if let Some(passphrase) = passphrase {
+        compressed = encryption::encrypt(&compressed, &passphrase)?;
+        header.flags.insert(Flags::ENCRYPTED);
+    }
+    let signature = key.map(|key| {
+        eprintln!("Signed by {}", key.public_key());
+        key.sign(&header, &compressed)
+    });
 
-    let mut image = Image::new(dimensions, pixels);
-    image.bake_payload(&payload);
+    let encoding = Encoding {
+        header,
+        layout,
+        density,
+        signature,
+        aspect: if square {
+            None
+        } else {
+            Some(baking.grid_size(dimensions)?)
+        },
+    };
+    let payloads = match frames {
+        Some(frames) => Payload::with_frames(&compressed, encoding, frames)?,
+        None => vec![Payload::with_encoding(&compressed, encoding)?],
+    };
 
-    let mut output_image = RgbaImage::new(dimensions.0, dimensions.1);
-    //let mut output_image = DynamicImage::new_rgba8(dimensions.0, dimensions.1);
+    let pixels = Pixel::from_rgba(&image).ok_or(imagemusic::image::Error::InvalidDimensions)?;
+    let original = Image::from_buffer(dimensions, pixels)?;
 
-    for (out_pixel, pixel) in output_image.pixels_mut().zip(image.pixels()) {
-        out_pixel[0] = pixel.r;
-        out_pixel[1] = pixel.g;
-        out_pixel[2] = pixel.b;
-        out_pixel[3] = pixel.a;
+    // Every frame is the same size, give or take a byte, so the first is as large as any.
+    let grid = (payloads[0].width(), payloads[0].height());
+    let (superpixel_width, superpixel_height) = baking.superpixel_size(dimensions, grid)?;
+    eprintln!(
+        "Grid is {}x{} superpixels of {}x{} pixels",
+        grid.0, grid.1, superpixel_width, superpixel_height
+    );
+    if superpixel_width.min(superpixel_height) < min_superpixel.unwrap_or(WARN_SUPERPIXEL) {
+        let minimum = min_superpixel.unwrap_or(WARN_SUPERPIXEL);
+        let capacity = Payload::capacity(dimensions, minimum, encoding, baking).unwrap_or(0);
+        let message = format!(
+            "Superpixels are smaller than {0}x{0} pixels, which only fits {1} bytes",
+            minimum, capacity
+        );
+        match min_superpixel {
+            Some(_) => return Err(message.into()),
+            None => eprintln!("{}, and may not read back", message),
+        }
+    }
+    let mut output_images = Vec::new();
+    for payload in &payloads {
+        // Baked straight into the output buffer.
+        let mut output_image = image.clone();
+        let pixels = Pixel::from_rgba_mut(&mut output_image)
+            .ok_or(imagemusic::image::Error::InvalidDimensions)?;
+        let mut baked = Image::from_buffer(dimensions, pixels)?;
+        baked.bake_payload_with(payload, baking)?;
+        if metrics {
+            eprintln!(
+                "PSNR {:.2} dB, SSIM {:.4}",
+                original.psnr(&baked)?,
+                original.ssim(&baked)?
+            );
+        }
+        output_images.push(output_image);
     }
 
-    DynamicImage::ImageRgba8(output_image).save(outputimagepath)?;
+    if frames.is_none() {
+        let output_image = output_images.remove(0);
+        DynamicImage::ImageRgba8(output_image).save(outputimagepath)?;
+    } else if outputimagepath.to_lowercase().ends_with(".gif") {
+        // GIF frames are cut down to a palette of 256 colors, which only the widest bands survive.
+        if density != Density::Robust {
+            eprintln!("GIF frames may not be readable at any density but robust");
+        }
+        let delay = Delay::from_numer_denom_ms(FRAME_DELAY_MS, 1);
+        GifEncoder::new(File::create(outputimagepath)?).encode_frames(
+            output_images
+                .into_iter()
+                .map(|output_image| Frame::from_parts(output_image, 0, 0, delay)),
+        )?;
+    } else {
+        save_apng(outputimagepath, &output_images)?;
+    }
 
     Ok(())
 }
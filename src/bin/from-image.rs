@@ -0,0 +1,183 @@
+use image::gif::GifDecoder;
+use image::png::PngDecoder;
+use image::{AnimationDecoder, DynamicImage, Frame, ImageFormat, RgbaImage};
+use imagemusic::encryption;
+use imagemusic::image::{Error, Image, Payload, Pixel};
+use imagemusic::signing::PublicKey;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+
+/// Every frame of an animated GIF or PNG, or just the image for anything else.
+fn load_frames(path: &str) -> Result<Vec<RgbaImage>, Box<dyn std::error::Error>> {
+    let bytes = fs::read(path)?;
+    let frames = match image::guess_format(&bytes)? {
+        ImageFormat::Gif => GifDecoder::new(Cursor::new(&bytes))?
+            .into_frames()
+            .collect_frames()?,
+        ImageFormat::Png if PngDecoder::new(Cursor::new(&bytes))?.is_apng() => {
+            PngDecoder::new(Cursor::new(&bytes))?
+                .apng()
+                .into_frames()
+                .collect_frames()?
+        }
+        _ => return Ok(vec![image::load_from_memory(&bytes)?.into_rgba()]),
+    };
+    Ok(frames.into_iter().map(Frame::into_buffer).collect())
+}
+
+/// Write an image out to a file, in whatever format its extension gives.
+fn save_image(image: &Image, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = image.dimensions();
+    let buffer = RgbaImage::from_raw(width, height, Pixel::as_rgba(image.pixels()).to_vec())
+        .ok_or("Image does not match its dimensions")?;
+    DynamicImage::ImageRgba8(buffer).save(path)?;
+    Ok(())
+}
+
+/// Print a song as TOML, noting who signed it, and failing if it wasn't signed by the expected
+/// signer.
+fn print_song(
+    payloads: &[Payload],
+    passphrase: Option<&str>,
+    signer: Option<PublicKey>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let unpacked = imagemusic::unpack_payloads(payloads, passphrase)?;
+    if let Some(expected) = signer {
+        match unpacked.signer {
+            Some(actual) if actual == expected => (),
+            Some(actual) => return Err(format!("Signed by {}, not {}", actual, expected).into()),
+            None => return Err(format!("Not signed, but expected {}", expected).into()),
+        }
+    }
+    if let Some(signer) = unpacked.signer {
+        println!("# Signed by {}", signer);
+    }
+    println!("{}", toml::to_string(&unpacked.song)?);
+    Ok(())
+}
+
+/// Read a song back out of an image, printing it as TOML
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        panic!("imagemusic {input image} [--confidence {output image}] [--annotate {output image}] [--all] [--passphrase {passphrase}] [--ask-passphrase] [--signer {public key}]");
+    }
+    let inputimagepath = &args[0];
+
+    let mut confidencepath = None;
+    let mut annotatepath = None;
+    let mut all = false;
+    let mut passphrase = None;
+    let mut signer = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            // A heatmap of how sure the read was of each superpixel.
+            "--confidence" => {
+                confidencepath = Some(
+                    options
+                        .next()
+                        .unwrap_or_else(|| panic!("--confidence needs an output image")),
+                )
+            }
+            // What the read made of the grid: its edges, the target, the value read from every
+            // superpixel, and which of them are padding.
+            "--annotate" => {
+                annotatepath = Some(
+                    options
+                        .next()
+                        .unwrap_or_else(|| panic!("--annotate needs an output image")),
+                )
+            }
+            // Every payload in the image, such as one per panel of a poster.
+            "--all" => all = true,
+            // For songs that were encrypted when they were baked.
+            "--passphrase" => {
+                passphrase = Some(
+                    options
+                        .next()
+                        .unwrap_or_else(|| panic!("--passphrase needs a passphrase"))
+                        .clone(),
+                )
+            }
+            "--ask-passphrase" => passphrase = Some(encryption::ask_passphrase()?),
+            // Only accept songs signed by this key, such as a contest entrant's.
+            "--signer" => {
+                signer = Some(PublicKey::from_hex(
+                    options
+                        .next()
+                        .unwrap_or_else(|| panic!("--signer needs a public key")),
+                )?)
+            }
+            option => panic!("Unknown option {}", option),
+        }
+    }
+
+    // Read in place, without copying the pixels out of the decoded frames.
+    let frames = load_frames(inputimagepath)?;
+    let mut images = frames
+        .iter()
+        .map(|frame| {
+            let pixels = Pixel::from_rgba(frame).ok_or(Error::InvalidDimensions)?;
+            Image::from_buffer(frame.dimensions(), pixels)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // A song spread over the frames of an animation, with one payload in each.
+    if images.len() > 1 {
+        if all || confidencepath.is_some() || annotatepath.is_some() {
+            panic!("--all, --confidence, and --annotate only work on still images");
+        }
+        let payloads = images
+            .iter()
+            .map(Image::read_payload)
+            .collect::<Result<Vec<_>, _>>()?;
+        return print_song(&payloads, passphrase.as_deref(), signer);
+    }
+
+    let image = images.remove(0);
+    if all {
+        if confidencepath.is_some() || annotatepath.is_some() {
+            panic!("--confidence and --annotate can't be combined with --all");
+        }
+        let readings = image.read_payloads();
+        if readings.is_empty() {
+            return Err("No payloads found".into());
+        }
+        for reading in readings {
+            let (x, y) = reading.corners()[0];
+            println!("# Song at ({}, {})", x.round(), y.round());
+            print_song(
+                std::slice::from_ref(&reading.payload),
+                passphrase.as_deref(),
+                signer,
+            )?;
+        }
+        return Ok(());
+    }
+
+    let reading = image.read_payload_with_confidence()?;
+
+    // Written before decoding, so that they're there to look at when decoding fails.
+    if let Some(confidencepath) = confidencepath {
+        save_image(&image.confidence_heatmap(&reading), confidencepath)?;
+    }
+    if let Some(annotatepath) = annotatepath {
+        let (superpixel_width, superpixel_height) = reading.grid.superpixel_size();
+        eprintln!(
+            "Grid is {}x{} superpixels of {:.1}x{:.1} pixels",
+            reading.payload.width(),
+            reading.payload.height(),
+            superpixel_width,
+            superpixel_height
+        );
+        save_image(&image.annotated(&reading), annotatepath)?;
+    }
+
+    print_song(
+        std::slice::from_ref(&reading.payload),
+        passphrase.as_deref(),
+        signer,
+    )
+}
@@ -0,0 +1,111 @@
+//! Optional passphrase encryption of compressed song data.
+//!
+//! The key is derived from the passphrase with Argon2id and a random salt, and the data is sealed
+//! with ChaCha20-Poly1305 under a random nonce, so a wrong passphrase or tampered data is always
+//! caught rather than decrypted into garbage.  The sealed data is laid out as the salt, then the
+//! nonce, then the ciphertext with its tag.
+
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::fmt;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// How many bytes encryption adds to the data.
+pub const OVERHEAD: usize = SALT_LEN + NONCE_LEN + TAG_LEN;
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum Error {
+    /// The data is encrypted, but no passphrase was given.
+    NeedsPassphrase,
+    /// The passphrase doesn't match the one the data was encrypted with, or the data was
+    /// tampered with.
+    WrongPassphrase,
+    /// The data is too short to hold a salt, nonce, and tag.
+    Truncated,
+    /// No random salt or nonce could be drawn.
+    Random,
+    /// No key could be derived from the passphrase.
+    KeyDerivation,
+    /// The data is too long to be sealed.
+    TooLong,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Derive the key for a passphrase and salt.
+fn key(passphrase: &str, salt: &[u8]) -> Result<Key, Error> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| Error::KeyDerivation)?;
+    Ok(key)
+}
+
+/// Seal data with a passphrase.
+pub fn encrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    let mut sealed = vec![0; SALT_LEN + NONCE_LEN];
+    getrandom::getrandom(&mut sealed).map_err(|_| Error::Random)?;
+    let (salt, nonce) = sealed.split_at(SALT_LEN);
+
+    let cipher = ChaCha20Poly1305::new(&key(passphrase, salt)?);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(nonce), data)
+        .map_err(|_| Error::TooLong)?;
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+/// Open data sealed with a passphrase.
+pub fn decrypt(sealed: &[u8], passphrase: &str) -> Result<Vec<u8>, Error> {
+    if sealed.len() < OVERHEAD {
+        return Err(Error::Truncated);
+    }
+    let (salt, rest) = sealed.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let cipher = ChaCha20Poly1305::new(&key(passphrase, salt)?);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::WrongPassphrase)
+}
+
+/// Prompt for a passphrase on the terminal and read it without echoing it, so that it doesn't
+/// end up on screen or in scrollback.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn ask_passphrase() -> std::io::Result<String> {
+    rpassword::prompt_password("Passphrase: ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let data = b"a song that must stay secret until the code is found";
+        let sealed = encrypt(data, "correct horse").expect("Could not encrypt");
+        assert_eq!(sealed.len(), data.len() + OVERHEAD);
+        assert_eq!(decrypt(&sealed, "correct horse"), Ok(data.to_vec()));
+        assert_eq!(
+            decrypt(&sealed, "battery staple"),
+            Err(Error::WrongPassphrase)
+        );
+        assert_eq!(
+            decrypt(&sealed[..SALT_LEN + NONCE_LEN], "correct horse"),
+            Err(Error::Truncated)
+        );
+
+        // Every encryption is salted differently.
+        assert_ne!(encrypt(data, "correct horse").unwrap(), sealed);
+    }
+}
@@ -81,6 +81,9 @@ impl ser::Serialize for Envelope {
     }
 }
 
+/// The most points to make room for before they have been read.
+const MAX_PREALLOCATED_POINTS: usize = 1024;
+
 struct EnvelopeVisitor;
 
 impl<'de> de::Visitor<'de> for EnvelopeVisitor {
@@ -94,8 +97,9 @@ impl<'de> de::Visitor<'de> for EnvelopeVisitor {
     where
         A: de::SeqAccess<'de>,
     {
+        // The size comes from the data, so it can't be trusted to allocate all at once.
         let mut points = match seq.size_hint() {
-            Some(size) => Vec::with_capacity(size),
+            Some(size) => Vec::with_capacity(size.min(MAX_PREALLOCATED_POINTS)),
             None => Vec::new(),
         };
         while let Some(point) = seq.next_element()? {
@@ -6,15 +6,64 @@
 //! The encoding process is roughly:
 //!
 //! * Take in a series of bytes to be the payload.
-//! * Calculate the size of the square grid that will be needed to encode that payload (+9 for the
-//!   size target).
+//! * Prefix it with a versioned header describing how it was encoded, and split it into
+//!   Reed-Solomon blocks.
+//! * Calculate the size of the square grid that will be needed to encode that payload, along with
+//!   the patterns of the chosen layout that let a reader find it (+9 for the size target).
 //! * Encode the bytes into an affinity array, with the width specified.
 
+mod annotate;
+mod calibration;
+mod capacity;
+mod confidence;
+mod density;
 mod error;
+mod error_correction;
+mod finder;
+mod header;
+mod homography;
+mod interleave;
+mod layout;
+mod locate;
+mod metrics;
+mod reed_solomon;
+#[cfg(test)]
+mod robustness;
+mod signature;
+pub use annotate::Role;
+pub use calibration::Calibration;
+pub use capacity::Plan;
+use confidence::Votes;
+pub use confidence::{Confidence, ConfidenceGrid, Reading};
+pub use density::Density;
+use density::MARKER_LEN;
 pub use error::Error;
-
-use std::collections::HashMap;
-use std::convert::TryInto;
+pub use error_correction::ErrorCorrection;
+use header::Parsed;
+pub use header::{Codec, Compression, Flags, Frame, Header, FORMAT_VERSION};
+pub use layout::Layout;
+pub use locate::Grid;
+pub use signature::{Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+
+use std::convert::TryFrom;
+
+/// Pixels with every channel below this are black, and pixels with every channel above
+/// [`WHITE_ABOVE`] are white, both when searching for the target and when reading values.
+pub(crate) const BLACK_BELOW: u8 = 16;
+pub(crate) const WHITE_ABOVE: u8 = 239;
+
+/// The target pattern, in spiral order.
+const TARGET: [Superpixel; 9] = [
+    Superpixel::Black,
+    Superpixel::White,
+    Superpixel::White,
+    Superpixel::White,
+    Superpixel::Black,
+    Superpixel::Black,
+    Superpixel::Black,
+    Superpixel::Black,
+    Superpixel::Black,
+];
 
 /// Superpixel affinity, determines whether a superpixel is black, white, or the value of the pixel
 /// itself.
@@ -22,8 +71,59 @@ use std::convert::TryInto;
 pub enum Superpixel {
     Black,
     White,
-    /// 1-64
-    Value(u8),
+    /// As many bits as the payload's density holds, 6 by default
+    Value(u16),
+}
+
+/// CRC32 of payload data.
+fn checksum(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// The grid position of the given index in spiral order.
+fn spiral_position(index: usize) -> (u32, u32) {
+    let radius = ceil_sqrt(index + 1) - 1;
+    let offset = (index - (radius as usize).pow(2)) as u32;
+    if offset <= radius {
+        (radius, offset)
+    } else {
+        (radius * 2 - offset, radius)
+    }
+}
+
+/// The grid position of the given index in the order of a grid with these columns and rows.  The
+/// square at the corner is in spiral order, and whatever sticks out past it is in rows across the
+/// grid when it is tall, or in columns down it when it is wide, so that a square grid is entirely
+/// in spiral order and the header of any grid is in its corner.
+fn grid_position(index: usize, (columns, rows): (u32, u32)) -> (u32, u32) {
+    let side = columns.min(rows);
+    let square = (side as usize).pow(2);
+    if index < square {
+        return spiral_position(index);
+    }
+    let offset = index - square;
+    if columns > rows {
+        (
+            side + (offset / rows as usize) as u32,
+            (offset % rows as usize) as u32,
+        )
+    } else {
+        (
+            (offset % columns as usize) as u32,
+            side + (offset / columns as usize) as u32,
+        )
+    }
+}
+
+/// The smallest width whose square holds the given count.
+fn ceil_sqrt(count: usize) -> u32 {
+    let mut width = (count as f64).sqrt() as u32;
+    while (width as usize).pow(2) < count {
+        width += 1;
+    }
+    width
 }
 
 /// A payload, representing encoded data ready to bake into an image.
@@ -48,105 +148,526 @@ pub enum Superpixel {
 /// width of 2, but when reading it, the decoder will find a 50x50 image.  Because the payload is
 /// length-prefixed, the extra superpixels will make no difference.
 ///
+/// Grids that aren't square carry on past the square at their corner in rows or columns, as in
+/// [`grid_position`], and give their columns and rows in the header, which sits in that square.
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct Payload {
-    width: u8,
+    width: u32,
+    height: u32,
+    layout: Layout,
+    density: Density,
     data: Vec<Superpixel>,
 }
 
+/// Everything about how a payload is laid out that a reader needs to know.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct Encoding {
+    pub header: Header,
+    pub layout: Layout,
+    pub density: Density,
+
+    /// A signature over the data, carried in front of it.  The header is flagged as signed when
+    /// this is given.
+    pub signature: Option<Signature>,
+
+    /// The aspect ratio the grid should follow as closely as whole superpixels allow, such as the
+    /// dimensions of the image it is baked into, so that a wide banner is filled by a wide grid.
+    /// The grid is square when this isn't given, and always with finder patterns, which are
+    /// fitted assuming it is.
+    pub aspect: Option<(u32, u32)>,
+}
+
+/// Where in an image a payload is baked.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Placement {
+    /// Stretched over the whole image.
+    #[default]
+    Whole,
+
+    /// In a rectangle of pixels, given by its top-left corner and its size.
+    Pixels {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+
+    /// In a rectangle given in fractions of the image's width and height, so that the same
+    /// placement suits an image of any size.
+    Fraction {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    },
+}
+
+impl Placement {
+    /// The corner, width, and height of the rectangle this covers in an image of the given
+    /// dimensions, or None if it is empty or doesn't fit inside the image.
+    pub fn rectangle(self, dimensions: (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+        let (x, y, width, height) = match self {
+            Placement::Whole => (0, 0, dimensions.0, dimensions.1),
+            Placement::Pixels {
+                x,
+                y,
+                width,
+                height,
+            } => (x, y, width, height),
+            Placement::Fraction {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let scale = |fraction: f32, size: u32| (fraction * size as f32).round();
+                let (left, top) = (scale(x, dimensions.0), scale(y, dimensions.1));
+                let (right, bottom) = (
+                    scale(x + width, dimensions.0),
+                    scale(y + height, dimensions.1),
+                );
+                if left < 0.0 || top < 0.0 || right < left || bottom < top {
+                    return None;
+                }
+                (
+                    left as u32,
+                    top as u32,
+                    (right - left) as u32,
+                    (bottom - top) as u32,
+                )
+            }
+        };
+        let fits = |start: u32, size: u32, total: u32| {
+            size > 0 && start.checked_add(size).is_some_and(|end| end <= total)
+        };
+        if fits(x, width, dimensions.0) && fits(y, height, dimensions.1) {
+            Some((x, y, width, height))
+        } else {
+            None
+        }
+    }
+}
+
+/// How each channel is moved into the band holding its value when baking.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum Rounding {
+    /// To the middle of the band, which leaves the most room for the image to be changed later,
+    /// but posterizes it.
+    #[default]
+    Center,
+
+    /// To the value nearest the original that stays at least a quarter band inside the band.
+    Nearest,
+
+    /// Like nearest, but what each pixel couldn't reach is spread over the pixels after it, with
+    /// Floyd-Steinberg error diffusion, so that areas keep their average color.
+    Diffused,
+}
+
+/// How a payload is drawn into an image, which a reader doesn't need to know.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Baking {
+    /// Superpixels are kept to whole multiples of this many pixels on each side, lined up from
+    /// the top-left corner of the image.  Whatever is left over past the payload is filled in
+    /// with junk values.
+    pub block: u32,
+
+    /// Fill each superpixel with a single color, the nearest one with its value to the average
+    /// of the pixels under it, instead of moving every pixel separately.  Neighboring pixels that
+    /// land in different periods blend into the wrong band when an image is compressed or
+    /// scaled, and a flat superpixel has none to blend.
+    pub flat: bool,
+
+    /// Where the payload goes.  Everything outside of it is left alone.
+    pub placement: Placement,
+
+    /// A border of this many white pixels kept around the payload, inside its placement, to set
+    /// it apart from the art around it.
+    pub quiet_zone: u32,
+
+    /// Split the placement into this many columns and rows of tiles, each holding its own copy
+    /// of the payload, with its own target and quiet zone.  The song then survives as long as
+    /// any copy does, and damaged copies are merged by the reader.
+    pub tiles: (u32, u32),
+
+    /// How channels are moved into their bands.
+    pub rounding: Rounding,
+}
+
+impl Baking {
+    /// Superpixels lined up with the 16x16 blocks that JPEG compresses subsampled color in, and
+    /// so also with the 8x8 blocks it compresses brightness in.  Each superpixel then only ever
+    /// shares a block with itself, so compression can blur it, but can't bleed its neighbors into
+    /// it.
+    ///
+    /// Superpixels are also baked flat, because JPEG blurs away the detail inside them anyway.
+    pub fn jpeg() -> Self {
+        Baking {
+            block: 16,
+            flat: true,
+            ..Baking::default()
+        }
+    }
+
+    /// The rectangles a payload is baked into within a rectangle, one for each tile.
+    pub(crate) fn tiles(
+        &self,
+        (left, top, width, height): (u32, u32, u32, u32),
+    ) -> Vec<(u32, u32, u32, u32)> {
+        let tiles = (self.tiles.0.max(1), self.tiles.1.max(1));
+        let edge = |start: u32, size: u32, count: u32, i: u32| {
+            start + (size as u64 * i as u64 / count as u64) as u32
+        };
+        let mut rectangles = Vec::new();
+        for row in 0..tiles.1 {
+            for column in 0..tiles.0 {
+                let x = edge(left, width, tiles.0, column);
+                let y = edge(top, height, tiles.1, row);
+                rectangles.push((
+                    x,
+                    y,
+                    edge(left, width, tiles.0, column + 1) - x,
+                    edge(top, height, tiles.1, row + 1) - y,
+                ));
+            }
+        }
+        rectangles
+    }
+
+    /// The origin and size of the grid within a rectangle, inside the quiet zone and starting on
+    /// a block boundary of the whole image.
+    pub(crate) fn grid_within(
+        &self,
+        (left, top, width, height): (u32, u32, u32, u32),
+    ) -> ((u32, u32), (u32, u32)) {
+        let block = self.block.max(1);
+        let quiet_zone = self.quiet_zone;
+        let origin = (
+            (left + quiet_zone).div_ceil(block) * block,
+            (top + quiet_zone).div_ceil(block) * block,
+        );
+        let end = (
+            (left + width).saturating_sub(quiet_zone),
+            (top + height).saturating_sub(quiet_zone),
+        );
+        (
+            origin,
+            (
+                end.0.saturating_sub(origin.0),
+                end.1.saturating_sub(origin.1),
+            ),
+        )
+    }
+
+    /// The size of the grid a payload is baked into in an image of these dimensions.  With
+    /// several tiles, this is the smallest of their grids.
+    pub fn grid_size(&self, dimensions: (u32, u32)) -> Result<(u32, u32), Error> {
+        let rectangle = self
+            .placement
+            .rectangle(dimensions)
+            .ok_or(Error::PlacementOutsideImage)?;
+        self.tiles(rectangle)
+            .into_iter()
+            .map(|tile| self.grid_within(tile).1)
+            .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1)))
+            .ok_or(Error::ImageTooSmall)
+    }
+
+    /// The size of the superpixels of a grid with these columns and rows, in a grid of this size.
+    pub(crate) fn superpixel_size_within(
+        &self,
+        (width, height): (u32, u32),
+        (columns, rows): (u32, u32),
+    ) -> Result<(u32, u32), Error> {
+        let block = self.block.max(1);
+        let size = (
+            width / columns.max(1) / block * block,
+            height / rows.max(1) / block * block,
+        );
+        if size.0 == 0 || size.1 == 0 {
+            Err(Error::ImageTooSmall)
+        } else {
+            Ok(size)
+        }
+    }
+
+    /// The size of the superpixels of a grid with these columns and rows, when baked into an
+    /// image of these dimensions.
+    pub fn superpixel_size(
+        &self,
+        dimensions: (u32, u32),
+        grid: (u32, u32),
+    ) -> Result<(u32, u32), Error> {
+        self.superpixel_size_within(self.grid_size(dimensions)?, grid)
+    }
+}
+
+impl Default for Baking {
+    fn default() -> Self {
+        Baking {
+            block: 1,
+            flat: false,
+            placement: Placement::default(),
+            quiet_zone: 0,
+            tiles: (1, 1),
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+/// Data read out of a payload, after any needed repairs.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct Decoded {
+    pub header: Header,
+    pub data: Vec<u8>,
+
+    /// The signature the data was baked with, if it was signed.  This has not been checked.
+    pub signature: Option<Signature>,
+
+    /// The number of superpixels that were damaged and had to be corrected.
+    pub corrected: usize,
+}
+
+impl Decoded {
+    /// Join the frames of a payload spread over several, in any order, back into the whole of
+    /// it.  Repeated frames are only used once, and a single unframed payload is returned as it
+    /// is.
+    pub fn join<I: IntoIterator<Item = Decoded>>(frames: I) -> Result<Decoded, Error> {
+        let mut frames: Vec<Decoded> = frames.into_iter().collect();
+        if frames.len() == 1 && frames[0].header.frame.is_none() {
+            return Ok(frames.remove(0));
+        }
+        let count = match frames.first().and_then(|decoded| decoded.header.frame) {
+            Some(frame) => frame.count,
+            None => return Err(Error::MissingFrame(0)),
+        };
+        let mut ordered: Vec<Option<Decoded>> = vec![None; count as usize];
+        for decoded in frames {
+            match decoded.header.frame {
+                Some(frame) if frame.count == count && frame.index < count => {
+                    ordered[frame.index as usize].get_or_insert(decoded);
+                }
+                _ => return Err(Error::MismatchedFrames),
+            }
+        }
+
+        let mut joined: Option<Decoded> = None;
+        for (index, decoded) in ordered.into_iter().enumerate() {
+            let decoded = decoded.ok_or(Error::MissingFrame(index as u16))?;
+            match &mut joined {
+                None => joined = Some(decoded),
+                Some(joined) => {
+                    joined.data.extend(decoded.data);
+                    joined.corrected += decoded.corrected;
+                }
+            }
+        }
+        // Only empty when the frames claim to be spread over none.
+        let mut joined = joined.ok_or(Error::MissingFrame(0))?;
+        // Every frame is laid out in a grid of its own, so the joined data has none.
+        joined.header.frame = None;
+        joined.header.flags.remove(Flags::FRAMED);
+        joined.header.grid = None;
+        joined.header.flags.remove(Flags::RECTANGULAR);
+        Ok(joined)
+    }
+}
+
 impl Payload {
-    pub fn new<B: AsRef<[u8]>>(input: B) -> Self {
-        let input = input.as_ref();
-        if input.len() > u16::MAX as usize {
-            panic!("Input can not be more than 65536 bytes long");
-        }
-        let mut to_encode = Vec::with_capacity(input.len() * 4 / 3 + 10);
-        to_encode.extend_from_slice(&(input.len() as u16).to_be_bytes());
-        to_encode.extend_from_slice(input);
-
-        // Initial target pattern
-        let mut data: Vec<Superpixel> = vec![
-            Superpixel::Black,
-            Superpixel::White,
-            Superpixel::White,
-            Superpixel::White,
-            Superpixel::Black,
-            Superpixel::Black,
-            Superpixel::Black,
-            Superpixel::Black,
-            Superpixel::Black,
-        ];
+    /// Encode the input as raw bytes with the default error correction level.
+    pub fn new<B: AsRef<[u8]>>(input: B) -> Result<Self, Error> {
+        Payload::with_header(input, Header::default())
+    }
 
-        for chunk in to_encode.chunks(3) {
-            // Make sure we always reserve 4 pixels at least
-            let mut chunk: Vec<u8> = chunk.into_iter().copied().collect();
+    pub fn with_error_correction<B: AsRef<[u8]>>(
+        input: B,
+        error_correction: ErrorCorrection,
+    ) -> Result<Self, Error> {
+        Payload::with_header(
+            input,
+            Header {
+                error_correction,
+                ..Header::default()
+            },
+        )
+    }
 
-            // Adding 0s on the end doesn't matter because of the length prefix.
-            chunk.resize(3, 0);
-            data.push(Superpixel::Value(chunk[0] >> 2));
-            data.push(Superpixel::Value(
-                ((chunk[0] & 0b00000011) << 4) | (chunk[1] >> 4),
-            ));
-            data.push(Superpixel::Value(
-                ((chunk[1] & 0b00001111) << 2) | (chunk[2] >> 6),
-            ));
-            data.push(Superpixel::Value(chunk[2] & 0b00111111));
+    /// Encode the input, describing it with the given header.
+    pub fn with_header<B: AsRef<[u8]>>(input: B, header: Header) -> Result<Self, Error> {
+        Payload::with_encoding(
+            input,
+            Encoding {
+                header,
+                ..Encoding::default()
+            },
+        )
+    }
+
+    /// Encode the input with full control over the header, layout, and density.
+    pub fn with_encoding<B: AsRef<[u8]>>(input: B, encoding: Encoding) -> Result<Self, Error> {
+        let mut header = encoding.header;
+        let mut signed = Vec::new();
+        if let Some(signature) = encoding.signature {
+            header.flags.insert(Flags::SIGNED);
+            signed = signature.to_bytes();
+        }
+        signed.extend_from_slice(input.as_ref());
+        let input = &signed[..];
+        let length = u32::try_from(input.len()).map_err(|_| Error::PayloadTooLarge)?;
+
+        let checksum = checksum(input);
+        let mut protected = header.error_correction.protect(input);
+        let dimensions = encoding.grid_for(protected.len());
+        header.grid = if dimensions.0 == dimensions.1 {
+            None
+        } else {
+            Some((
+                u16::try_from(dimensions.0).map_err(|_| Error::PayloadTooLarge)?,
+                u16::try_from(dimensions.1).map_err(|_| Error::PayloadTooLarge)?,
+            ))
+        };
+        let mut to_encode = header.encode(length, checksum);
+        if header.flags.contains(Flags::INTERLEAVED) {
+            interleave::interleave(&mut protected, checksum);
         }
+        to_encode.extend(protected);
+        Ok(Payload::from_bytes(
+            &to_encode,
+            encoding.layout,
+            encoding.density,
+            dimensions,
+        ))
+    }
 
-        // needed width
-        let width = (data.len() as f32).sqrt().ceil() as u8;
+    /// Encode the input spread evenly over a number of frames, such as the frames of an
+    /// animation, each a payload of its own that says where it sits among them.  A signature is
+    /// only carried by the first frame, and covers the whole input.  At least one frame is made.
+    pub fn with_frames<B: AsRef<[u8]>>(
+        input: B,
+        encoding: Encoding,
+        count: u16,
+    ) -> Result<Vec<Self>, Error> {
+        let input = input.as_ref();
+        let count = count.max(1);
+        let chunk_len = input.len().div_ceil(count as usize).max(1);
+        (0..count)
+            .map(|index| {
+                let start = (index as usize * chunk_len).min(input.len());
+                let end = (start + chunk_len).min(input.len());
+                Payload::with_encoding(
+                    &input[start..end],
+                    Encoding {
+                        header: Header {
+                            frame: Some(Frame { index, count }),
+                            ..encoding.header
+                        },
+                        signature: encoding.signature.filter(|_| index == 0),
+                        ..encoding
+                    },
+                )
+            })
+            .collect()
+    }
 
-        let mut value = 0u8;
+    /// Lay out already-encoded bytes around the layout's patterns, behind the density marker, in
+    /// a grid of these columns and rows.
+    fn from_bytes(
+        to_encode: &[u8],
+        layout: Layout,
+        density: Density,
+        (width, height): (u32, u32),
+    ) -> Self {
+        let values = density.bytes_to_values(to_encode);
 
         // All the extra superpixels are filled with random junk
-        data.resize_with(width as usize * width as usize, move || {
-            value = (value + 1) % 64;
-            Superpixel::Value(value)
-        });
+        let modulus = 1usize << density.bits();
+        let mut values = density.marker().to_vec().into_iter().chain(
+            values
+                .into_iter()
+                .chain((1..).map(move |value: usize| (value % modulus) as u16))
+                .map(Superpixel::Value),
+        );
 
         let mut payload = Payload {
             width,
-            data: vec![Superpixel::White; width as usize * width as usize],
+            height,
+            layout,
+            density,
+            data: vec![Superpixel::White; width as usize * height as usize],
         };
 
-        for (dest, src) in payload
-            .unraveled_payload_mut()
-            .into_iter()
-            .zip(data.into_iter())
-        {
-            *dest = src;
+        for (i, dest) in payload.unraveled_payload_mut().into_iter().enumerate() {
+            let (x, y) = grid_position(i, (width, height));
+            if let Some(superpixel) = layout.pattern(width, x, y).or_else(|| values.next()) {
+                *dest = superpixel;
+            }
         }
 
         payload
     }
 
-    /// Takes in data as raw superpixels and width, checking the target and vector size.
-    /// This is taken in normal row-major order, not the corner spiral.
-    pub fn from_raw<V: Into<Vec<Superpixel>>>(width: u8, data: V) -> Result<Self, Error> {
+    /// Takes in data as raw superpixels and width, checking the layout patterns, density marker,
+    /// and vector size.  This is taken in normal row-major order, not the corner spiral.
+    ///
+    /// Values are expected to have been read at the density the marker gives.
+    pub fn from_raw<V: Into<Vec<Superpixel>>>(width: u32, data: V) -> Result<Self, Error> {
+        Payload::from_raw_grid((width, width), data)
+    }
+
+    /// Takes in data as raw superpixels of a grid with these columns and rows, as with
+    /// [`Payload::from_raw`].
+    pub fn from_raw_grid<V: Into<Vec<Superpixel>>>(
+        (width, height): (u32, u32),
+        data: V,
+    ) -> Result<Self, Error> {
         let data = data.into();
-        if data.len() != (width as usize).pow(2) {
+        if data.len() as u64 != width as u64 * height as u64 {
             return Err(Error::InvalidDimensions);
         }
 
-        let payload = Payload { width, data };
-
-        if &payload.unraveled_payload()[..9]
-            == &[
-                Superpixel::Black,
-                Superpixel::White,
-                Superpixel::White,
-                Superpixel::White,
-                Superpixel::Black,
-                Superpixel::Black,
-                Superpixel::Black,
-                Superpixel::Black,
-                Superpixel::Black,
-            ]
-        {
-            Ok(payload)
-        } else {
-            Err(Error::NoTargetFound)
-        }
+        let layout = *Layout::ALL
+            .iter()
+            .find(|layout| layout.matches((width, height), &data))
+            .ok_or(Error::NoTargetFound)?;
+        let mut payload = Payload {
+            width,
+            height,
+            layout,
+            density: Density::default(),
+            data,
+        };
+        payload.density = payload
+            .cells()
+            .get(..MARKER_LEN)
+            .and_then(Density::from_marker)
+            .ok_or(Error::UnknownDensity)?;
+        Ok(payload)
+    }
+
+    /// The layout of the patterns that let a reader find this payload.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// The number of bits each superpixel carries.
+    pub fn density(&self) -> Density {
+        self.density
+    }
+
+    /// The width of the grid, in superpixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the grid, in superpixels.  This is the same as the width unless the grid
+    /// was laid out to follow an aspect ratio.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The columns and rows of the grid.
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
     }
 
     pub fn rows(&self) -> impl Iterator<Item = impl Iterator<Item = &Superpixel>> {
@@ -155,13 +676,14 @@ impl Payload {
             .map(|chunk| chunk.into_iter())
     }
 
-    /// Get the indexed row, always grabbing the last if over.
+    /// Get the indexed row, always grabbing the last if over.  An empty payload has only empty
+    /// rows.
     pub fn row(&self, index: usize) -> Vec<&Superpixel> {
-        if index >= self.width as usize {
-            self.rows().last().unwrap().collect()
-        } else {
-            self.rows().skip(index).next().unwrap().collect()
-        }
+        let index = index.min((self.height as usize).saturating_sub(1));
+        self.rows()
+            .nth(index)
+            .map(Iterator::collect)
+            .unwrap_or_default()
     }
 
     pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = &Superpixel>> {
@@ -169,39 +691,32 @@ impl Payload {
             .map(move |column| self.data.iter().skip(column).step_by(self.width as usize))
     }
 
-    /// Get the column as indexed, always grabbing the last if needed.
+    /// Get the column as indexed, always grabbing the last if needed.  An empty payload has only
+    /// empty columns.
     pub fn column(&self, index: usize) -> Vec<&Superpixel> {
-        if index >= self.width as usize {
-            self.columns().last().unwrap().collect()
-        } else {
-            self.columns().skip(index).next().unwrap().collect()
-        }
+        let index = index.min((self.width as usize).saturating_sub(1));
+        self.columns()
+            .nth(index)
+            .map(Iterator::collect)
+            .unwrap_or_default()
     }
 
     /// Get the indexed superpixel.  When out of bounds, the last superpixel in that direction will
     /// be selected.
     pub fn get_superpixel(&self, x: usize, y: usize) -> &Superpixel {
-        let column = self.column(x);
-        if y >= column.len() {
-            column.last().unwrap()
-        } else {
-            &column[y]
-        }
+        let x = x.min((self.width as usize).saturating_sub(1));
+        let y = y.min((self.height as usize).saturating_sub(1));
+        &self.data[y * self.width as usize + x]
     }
 
     /// Unravels the payload, returning it in wound data order.
     pub fn unraveled_payload(&self) -> Vec<Superpixel> {
-        let mut output = Vec::new();
-        for radius in 0..self.width {
-            for row in 0..=radius {
-                output.push(self.data[row as usize * self.width as usize + radius as usize]);
-            }
-
-            for column in (0..radius).rev() {
-                output.push(self.data[radius as usize * self.width as usize + column as usize]);
-            }
-        }
-        output
+        (0..self.data.len())
+            .map(|i| {
+                let (x, y) = grid_position(i, self.dimensions());
+                self.data[y as usize * self.width as usize + x as usize]
+            })
+            .collect()
     }
 
     /// Unravels the payload, returning it in wound data order.
@@ -209,76 +724,150 @@ impl Payload {
     /// This is private because modifying a loaded payload to remove the target or corrupt the
     /// length or anything like that would be bad behavior.
     fn unraveled_payload_mut(&mut self) -> Vec<&mut Superpixel> {
-        let mut data_vec: Vec<Option<&mut Superpixel>> =
-            self.data.iter_mut().map(|s| Some(s)).collect();
-        let mut output = Vec::new();
-        for radius in 0..self.width {
-            for row in 0..=radius {
-                output.push(
-                    data_vec[row as usize * self.width as usize + radius as usize]
-                        .take()
-                        .unwrap(),
-                );
-            }
-
-            for column in (0..radius).rev() {
-                output.push(
-                    data_vec[radius as usize * self.width as usize + column as usize]
-                        .take()
-                        .unwrap(),
-                );
-            }
-        }
-        output
+        let dimensions = self.dimensions();
+        let mut data_vec: Vec<Option<&mut Superpixel>> = self.data.iter_mut().map(Some).collect();
+        (0..data_vec.len())
+            .map(|i| {
+                let (x, y) = grid_position(i, dimensions);
+                data_vec[y as usize * dimensions.0 as usize + x as usize]
+                    .take()
+                    .unwrap()
+            })
+            .collect()
     }
 
-    /// Read the data out of this packed payload
+    /// Read the data out of this packed payload, repairing it if needed.
     pub fn data(&self) -> Result<Vec<u8>, Error> {
-        let data = self.unraveled_payload();
-        // skip target
-        let data: Vec<_> = data[9..].into_iter().collect();
+        self.decode().map(|decoded| decoded.data)
+    }
 
-        let data: Vec<u8> = data
+    /// The superpixels outside the layout's patterns, in spiral order.
+    fn cells(&self) -> Vec<Superpixel> {
+        self.unraveled_payload()
             .into_iter()
-            .map(|superpixel| {
-                use Superpixel::*;
-                match superpixel {
-                    Black => 0,
-                    White => 63,
-                    Value(value) => value & 0b00111111,
-                }
+            .enumerate()
+            .filter(|&(i, _)| {
+                let (x, y) = grid_position(i, self.dimensions());
+                self.layout.pattern(self.width, x, y).is_none()
             })
-            .collect();
+            .map(|(_, superpixel)| superpixel)
+            .collect()
+    }
 
-        let mut output = Vec::with_capacity(data.len());
+    /// The values held after the density marker, in spiral order.
+    fn values(&self) -> Vec<u16> {
+        self.cells()[MARKER_LEN..]
+            .iter()
+            .map(|&superpixel| self.density.value(superpixel))
+            .collect()
+    }
+
+    /// Read only the header of this packed payload.
+    pub fn header(&self) -> Result<Header, Error> {
+        self.parse_header().map(|parsed| parsed.header)
+    }
 
-        for chunk in data.chunks(4) {
-            let mut chunk: Vec<u8> = chunk.into_iter().copied().collect();
-            // Extra 0s shouldn't really matter, but this simplifies decoding
-            chunk.resize(4, 0);
+    /// Read and check the header, along with the length and checksum it gives.
+    fn parse_header(&self) -> Result<Parsed, Error> {
+        Header::decode(&mut self.density.values_to_bytes(&self.values()))
+    }
+
+    /// The columns and rows of the smallest payload holding everything a header says was
+    /// encoded.  Grids that aren't square are given by the header.
+    fn dimensions_for(layout: Layout, density: Density, parsed: &Parsed) -> (u32, u32) {
+        if let Some((columns, rows)) = parsed.header.grid {
+            return (columns as u32, rows as u32);
+        }
+        let bytes = parsed.header_len
+            + parsed
+                .header
+                .error_correction
+                .protected_len(parsed.length as usize);
+        let values = (bytes * 8).div_ceil(density.bits() as usize);
+        let width = layout.width_for(MARKER_LEN + values);
+        (width, width)
+    }
 
-            output.push((chunk[0] << 2) | (chunk[1] >> 4));
-            output.push((chunk[1] << 4) | (chunk[2] >> 2));
-            output.push((chunk[2] << 6) | chunk[3]);
+    /// The columns and rows of the smallest payload holding everything the header says was
+    /// encoded.  This is less than was read when the grid carries on past the payload, into the
+    /// art around it.
+    pub(crate) fn encoded_dimensions(&self) -> Result<(u32, u32), Error> {
+        let parsed = self.parse_header()?;
+        // Finder patterns are fitted assuming the grid is square.
+        if parsed.header.grid.is_some() && self.layout == Layout::Finders {
+            return Err(Error::SuperpixelGridNotSquare);
         }
+        let (columns, rows) = Payload::dimensions_for(self.layout, self.density, &parsed);
+        Ok((columns.min(self.width), rows.min(self.height)))
+    }
 
-        let length = u16::from_be_bytes(output[0..2].try_into().unwrap());
-        // Remove length
-        output.remove(0);
-        output.remove(0);
-        if length as usize > output.len() {
-            Err(Error::InvalidLength {
+    /// Read the data out of this packed payload, also reporting how much of it had to be repaired.
+    ///
+    /// Damage that error correction can't repair, but also doesn't notice, is caught by the
+    /// checksum.
+    pub fn decode(&self) -> Result<Decoded, Error> {
+        let values = self.values();
+        let mut output = self.density.values_to_bytes(&values);
+
+        let Parsed {
+            header,
+            length,
+            checksum,
+            header_len,
+        } = Header::decode(&mut output)?;
+
+        let protected = &mut output[header_len..];
+        let protected_len = header.error_correction.protected_len(length as usize);
+        if protected_len > protected.len() {
+            return Err(Error::InvalidLength {
                 encoded: length,
-                available: output.len() as u16,
-            })
-        } else {
-            output.resize(length as usize, 0);
-            Ok(output)
+                available: protected.len() as u32,
+            });
+        }
+
+        let protected = &mut protected[..protected_len];
+        let interleaved = header.flags.contains(Flags::INTERLEAVED);
+        if interleaved {
+            interleave::deinterleave(protected, checksum);
+        }
+        let data = header.error_correction.repair(protected, length as usize)?;
+        // Scattered back again, so that the repaired bytes line up with the superpixels read.
+        if interleaved {
+            interleave::interleave(protected, checksum);
+        }
+        if self::checksum(&data) != checksum {
+            return Err(Error::ChecksumMismatch);
         }
+        let (signature, data) = if header.flags.contains(Flags::SIGNED) {
+            let (signature, data) = Signature::split(&data)?;
+            (Some(signature), data.to_vec())
+        } else {
+            (None, data)
+        };
+
+        // Re-encode the repaired bytes to find which superpixels were actually wrong.
+        let repaired_len = header_len + protected_len;
+        let corrected = self
+            .density
+            .bytes_to_values(&output[..repaired_len])
+            .into_iter()
+            .zip(values.iter())
+            .filter(|(repaired, read)| repaired != *read)
+            .count();
+
+        Ok(Decoded {
+            header,
+            data,
+            signature,
+            corrected,
+        })
     }
 }
 
+/// One RGBA pixel.  It is laid out the same as four bytes of RGBA data, so that image buffers can
+/// be viewed as pixels in place with [`Pixel::from_rgba`] and [`Pixel::from_rgba_mut`].
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(C)]
 pub struct Pixel {
     pub r: u8,
     pub g: u8,
@@ -286,53 +875,138 @@ pub struct Pixel {
     pub a: u8,
 }
 
+// The casts between pixels and bytes rely on this.
+const _: () = assert!(
+    std::mem::size_of::<Pixel>() == 4 && std::mem::align_of::<Pixel>() == 1,
+    "Pixel must be laid out as four bytes"
+);
+
+impl Pixel {
+    /// View RGBA data, four bytes to a pixel, as pixels without copying it.  Returns `None` if
+    /// the data isn't a whole number of pixels.
+    pub fn from_rgba(data: &[u8]) -> Option<&[Pixel]> {
+        let len = data.len() / 4;
+        if len * 4 != data.len() {
+            return None;
+        }
+        // Pixel is four bytes with an alignment of one, so any four bytes are a valid pixel.
+        Some(unsafe { std::slice::from_raw_parts(data.as_ptr() as *const Pixel, len) })
+    }
+
+    /// View RGBA data as pixels that can be changed in place, as with [`Pixel::from_rgba`].
+    pub fn from_rgba_mut(data: &mut [u8]) -> Option<&mut [Pixel]> {
+        let len = data.len() / 4;
+        if len * 4 != data.len() {
+            return None;
+        }
+        Some(unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut Pixel, len) })
+    }
+
+    /// View pixels as RGBA data, four bytes to a pixel, without copying them.
+    pub fn as_rgba(pixels: &[Pixel]) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(pixels.as_ptr() as *const u8, pixels.len() * 4) }
+    }
+
+    pub(crate) fn is_black(self) -> bool {
+        self.r < BLACK_BELOW && self.g < BLACK_BELOW && self.b < BLACK_BELOW
+    }
+
+    pub(crate) fn is_white(self) -> bool {
+        self.r > WHITE_ABOVE && self.g > WHITE_ABOVE && self.b > WHITE_ABOVE
+    }
+}
+
+/// One way of finding candidate grids in an image.
+type Search<P> = fn(&Image<P>) -> Vec<Grid>;
+
+/// An image to bake payloads into or read them out of.
+///
+/// The pixels may be held in anything that can be viewed as a slice of them, such as a `Vec` of
+/// its own, or a borrowed RGBA buffer viewed with [`Pixel::from_rgba_mut`], which lets payloads
+/// be baked and read in place without copying the image.
 #[derive(Debug, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
-pub struct Image {
+pub struct Image<P = Vec<Pixel>> {
     dimensions: (u32, u32),
-    pixels: Vec<Pixel>,
+    pixels: P,
+}
+
+/// Round a value to the nearest one with the given affinity at the given density, in the middle
+/// of its band.
+/// Affinity must be less than the density's number of levels, or this will panic.
+fn round_to_affinity(density: Density, affinity: u8, input: u8) -> u8 {
+    round_within_band(density, affinity, input as f32, density.band() / 2)
 }
 
-/// Round a value to an affinity sixteenth
-/// Affinity must be [0, 3], or this will panic.
-fn round_to_affinity(affinity: u8, input: u8) -> u8 {
-    if affinity > 3 {
-        panic!("Affinity must be less than 4");
+/// Round a value to the nearest one with the given affinity at the given density, keeping at least
+/// `margin` inside the edges of its band so that it can drift that far either way and still read
+/// the same.  A margin of half a band always gives the middle of a band.
+/// Affinity must be less than the density's number of levels, or this will panic.
+fn round_within_band(density: Density, affinity: u8, input: f32, margin: u16) -> u8 {
+    let levels = density.levels();
+    if affinity as u16 >= levels {
+        panic!("Affinity must be less than {}", levels);
     }
-    let offset = 8 + 16 * affinity;
+    let period = density.period();
+    let band = density.band();
+    let margin = margin.min(band / 2);
+    let start = band * affinity as u16;
 
     // (Distance from value, value)
-    let value = (0u8..4u8)
-        .map(|quadrant| {
-            let value = quadrant * 64 + offset;
-            let distance = value.max(input) - value.min(input);
-            (distance, value)
+    let value = (0..256 / period)
+        .map(|quadrant| quadrant * period + start)
+        // Bands narrower than the black and white thresholds must stay clear of them, or a value
+        // could be misread as black or white.  So must grayscale bands, because all three
+        // channels cross the thresholds together.
+        .filter(|&start| {
+            (band >= 16 && !density.is_grayscale()) || (start >= 16 && start + band <= 240)
         })
-        .min_by_key(|(distance, _)| *distance)
+        .map(|start| {
+            // A band has no middle value, so the middle is taken to be just above halfway.
+            let low = start + margin;
+            let high = (start + band - 1 - margin).max(low);
+            let value = input.round().clamp(low as f32, high as f32);
+            let distance = (value - input).abs();
+            (distance, value as u16)
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
         .unwrap()
         .1;
 
-    // Floor and ceil values near edge where possible.  anything below 8 or above 248 in
-    // here shouldn't be possible, but this is more semantically clear.
-    match value {
-        0..=8 => 0,
-        248..=255 => 255,
-        value => value,
+    // Floor and ceil values near edge where possible.  Nothing past the middle of the outermost
+    // bands can come out of here, but this is more semantically clear.
+    if value <= band / 2 {
+        0
+    } else if value >= 256 - band / 2 {
+        255
+    } else {
+        value.min(255) as u8
     }
 }
 
-/// Find the affinity of a color value.
-/// output is always 0..4
-fn get_affinity(input: u8) -> u8 {
-    (input & 0b00110000) >> 4
+/// Find the affinity of a color value at the given density.
+/// output is always less than the density's number of levels
+fn get_affinity(density: Density, input: u8) -> u16 {
+    (input as u16 % density.period()) / density.band()
 }
 
 impl Pixel {
-    /// Bake the value into this pixel
-    pub fn with_value(self, value: u8) -> Pixel {
+    /// Bake the value into this pixel at the standard density
+    pub fn with_value(self, value: u16) -> Pixel {
+        self.with_value_at(value, Density::Standard)
+    }
+
+    /// Bake the value into this pixel at the given density
+    pub fn with_value_at(self, value: u16, density: Density) -> Pixel {
+        if density.is_grayscale() {
+            let affinity = (value & (density.levels() - 1)) as u8;
+            return self.with_luma(round_to_affinity(density, affinity, self.luma()));
+        }
+        let bits = density.bits_per_channel();
+        let mask = density.levels() - 1;
         Pixel {
-            r: round_to_affinity((value & 0b00110000) >> 4, self.r),
-            g: round_to_affinity((value & 0b00001100) >> 2, self.g),
-            b: round_to_affinity(value & 0b00000011, self.b),
+            r: round_to_affinity(density, ((value >> (bits * 2)) & mask) as u8, self.r),
+            g: round_to_affinity(density, ((value >> bits) & mask) as u8, self.g),
+            b: round_to_affinity(density, (value & mask) as u8, self.b),
             // no transparent pixels to prevent optimization from killing our encoded
             // data.
             // Optimization may still kill our encoded data if transparency is
@@ -341,31 +1015,130 @@ impl Pixel {
         }
     }
 
-    /// Get the value encoded in this pixel
+    /// Like `with_value_at`, but moving each channel to the value nearest the target color that
+    /// stays a quarter band inside its band, instead of to the middle of it.  Grayscale bands
+    /// have no room to spare after the color drift they allow, so they still go to the middle.
+    fn with_value_near(self, value: u16, density: Density, target: [f32; 3]) -> Pixel {
+        if density.is_grayscale() {
+            return self.with_value_at(value, density);
+        }
+        let bits = density.bits_per_channel();
+        let mask = density.levels() - 1;
+        let margin = density.band() / 4;
+        let channel = |shift: u32, target: f32| {
+            round_within_band(density, ((value >> shift) & mask) as u8, target, margin)
+        };
+        Pixel {
+            r: channel(bits * 2, target[0]),
+            g: channel(bits, target[1]),
+            b: channel(0, target[2]),
+            a: self.a.max(25),
+        }
+    }
+
+    /// Rec. 601 luma of this pixel.
+    fn luma(self) -> u8 {
+        ((self.r as u32 * 299 + self.g as u32 * 587 + self.b as u32 * 114 + 500) / 1000) as u8
+    }
+
+    /// Move this pixel to the given luma, keeping as much of its color as can be kept.
+    ///
+    /// Color is scaled down until it fits in range, and until the other common ways of turning
+    /// color into gray, Rec. 709 luma and the plain channel average, land within a quarter band
+    /// of the luma.  Every one of them then reads back the same value.
+    fn with_luma(self, luma: u8) -> Pixel {
+        let luma_601 = [0.299, 0.587, 0.114];
+        let luma_709 = [0.2126, 0.7152, 0.0722];
+        let average = [1.0 / 3.0; 3];
+        let channels = [self.r as f32, self.g as f32, self.b as f32];
+        let gray: f32 = channels
+            .iter()
+            .zip(luma_601.iter())
+            .map(|(channel, weight)| channel * weight)
+            .sum();
+        let chroma = [channels[0] - gray, channels[1] - gray, channels[2] - gray];
+        let luma = luma as f32;
+
+        let mut scale = 1.0f32;
+        for &offset in &chroma {
+            if luma + offset > 255.0 {
+                scale = scale.min((255.0 - luma) / offset);
+            } else if luma + offset < 0.0 {
+                scale = scale.min(luma / -offset);
+            }
+        }
+        let tolerance = Density::Grayscale.band() as f32 / 4.0;
+        for weights in &[luma_709, average] {
+            let drift: f32 = chroma
+                .iter()
+                .zip(weights.iter())
+                .map(|(offset, weight)| offset * weight)
+                .sum();
+            if drift.abs() > tolerance {
+                scale = scale.min(tolerance / drift.abs());
+            }
+        }
+
+        let channel = |offset: f32| (luma + offset * scale).round().clamp(0.0, 255.0) as u8;
+        Pixel {
+            r: channel(chroma[0]),
+            g: channel(chroma[1]),
+            b: channel(chroma[2]),
+            a: self.a.max(25),
+        }
+    }
+
+    /// Get the value encoded in this pixel at the standard density
     pub fn value(self) -> Superpixel {
+        self.value_at(Density::Standard)
+    }
+
+    /// Get the value encoded in this pixel at the given density
+    pub fn value_at(self, density: Density) -> Superpixel {
         // We can't just return the affinitie'd value and assume black for 0 and white for 63,
         // because 0 may not be black, due to quadrants, and 63 may not be white.
-        if self.r < 16 && self.g < 16 && self.b < 16 {
+        if self.is_black() {
             Superpixel::Black
-        } else if self.r > 239 && self.g > 239 && self.b > 239 {
+        } else if self.is_white() {
             Superpixel::White
+        } else if density.is_grayscale() {
+            Superpixel::Value(get_affinity(density, self.luma()))
         } else {
-            let r = get_affinity(self.r);
-            let g = get_affinity(self.g);
-            let b = get_affinity(self.b);
-            Superpixel::Value((r << 4) | (g << 2) | b)
+            let bits = density.bits_per_channel();
+            let r = get_affinity(density, self.r);
+            let g = get_affinity(density, self.g);
+            let b = get_affinity(density, self.b);
+            Superpixel::Value((r << (bits * 2)) | (g << bits) | b)
         }
     }
 }
 
 impl Image {
-    /// Panics if pixels length does not match dimensions.
+    /// Wrap pixels in row-major order.
+    ///
+    /// # Panics
+    ///
+    /// When the number of pixels doesn't match the dimensions.  Use [`Image::try_new`] for pixels
+    /// that haven't been checked.
     pub fn new<P: Into<Vec<Pixel>>>(dimensions: (u32, u32), pixels: P) -> Self {
-        let pixels = pixels.into();
-        if pixels.len() != dimensions.0 as usize * dimensions.1 as usize {
-            panic!("Pixels must match in size");
+        Image::try_new(dimensions, pixels).expect("Pixels must match in size")
+    }
+
+    /// Wrap pixels in row-major order, failing if their number doesn't match the dimensions.
+    pub fn try_new<P: Into<Vec<Pixel>>>(dimensions: (u32, u32), pixels: P) -> Result<Self, Error> {
+        let pixels: Vec<Pixel> = pixels.into();
+        Image::from_buffer(dimensions, pixels)
+    }
+}
+
+impl<P: AsRef<[Pixel]>> Image<P> {
+    /// Wrap a buffer of pixels in row-major order without copying them, failing if their number
+    /// doesn't match the dimensions.
+    pub fn from_buffer(dimensions: (u32, u32), pixels: P) -> Result<Self, Error> {
+        if pixels.as_ref().len() as u64 != dimensions.0 as u64 * dimensions.1 as u64 {
+            return Err(Error::InvalidDimensions);
         }
-        Image { dimensions, pixels }
+        Ok(Image { dimensions, pixels })
     }
 
     pub fn dimensions(&self) -> (u32, u32) {
@@ -373,26 +1146,156 @@ impl Image {
     }
 
     pub fn pixels(&self) -> &[Pixel] {
-        &self.pixels
+        self.pixels.as_ref()
+    }
+
+    /// Give back the buffer the pixels are held in.
+    pub fn into_buffer(self) -> P {
+        self.pixels
     }
+}
 
+impl<P: AsRef<[Pixel]> + AsMut<[Pixel]>> Image<P> {
     /// Bake a payload into this image.
-    pub fn bake_payload(&mut self, payload: &Payload) {
-        // Width is squared, so we determine the pixel width of each superpixel.  This will almost
-        // certainly not be perfect.  In the case that there is remainder, the last superpixel in
-        // that dimension will be stretched to the edge of the image.
-        let superpixel_width = self.dimensions.0 / payload.width as u32;
-        let superpixel_height = self.dimensions.1 / payload.width as u32;
-
-        for (i, pixel) in self.pixels.iter_mut().enumerate() {
-            let i = i as u32;
-            let x = i % self.dimensions.0;
-            let y = i / self.dimensions.0;
-
-            let superpixel = payload.get_superpixel(
-                x as usize / superpixel_width as usize,
-                y as usize / superpixel_height as usize,
-            );
+    ///
+    /// Fails if the image has fewer pixels in either dimension than the payload has superpixels.
+    pub fn bake_payload(&mut self, payload: &Payload) -> Result<(), Error> {
+        self.bake_payload_with(payload, Baking::default())
+    }
+
+    /// Bake the payload into this image, drawing it as the baking options say.
+    pub fn bake_payload_with(&mut self, payload: &Payload, baking: Baking) -> Result<(), Error> {
+        let rectangle = baking
+            .placement
+            .rectangle(self.dimensions)
+            .ok_or(Error::PlacementOutsideImage)?;
+        let (left, top, width, height) = rectangle;
+        let (right, bottom) = (left + width, top + height);
+
+        if baking.tiles.0.max(1) * baking.tiles.1.max(1) > 1 {
+            for (x, y, width, height) in baking.tiles(rectangle) {
+                let tile = Baking {
+                    placement: Placement::Pixels {
+                        x,
+                        y,
+                        width,
+                        height,
+                    },
+                    tiles: (1, 1),
+                    ..baking
+                };
+                self.bake_payload_with(payload, tile)?;
+            }
+            return Ok(());
+        }
+
+        // The grid starts inside the quiet zone, on a block boundary of the whole image.
+        let block = baking.block.max(1);
+        let (origin, (width, height)) = baking.grid_within(rectangle);
+        let end = (origin.0 + width, origin.1 + height);
+
+        // Determine the pixel size of each superpixel.  This will almost certainly not be
+        // perfect.  In the case that there is remainder, the last superpixel in that dimension
+        // will be stretched to the edge of the grid.
+        let (superpixel_width, superpixel_height) =
+            baking.superpixel_size_within((width, height), payload.dimensions())?;
+
+        // The superpixel under a pixel, in coordinates from the grid's origin.
+        let cell = |x: u32, y: u32| {
+            if block > 1 {
+                // Stretching or spreading would break the blocks, so the grid carries on past
+                // the payload instead.
+                (x / superpixel_width, y / superpixel_height)
+            } else {
+                match payload.layout {
+                    Layout::Target => (
+                        (x / superpixel_width).min(payload.width - 1),
+                        (y / superpixel_height).min(payload.height - 1),
+                    ),
+                    // Finder patterns are fitted assuming every superpixel is the same size, so
+                    // the remainder is spread out instead.
+                    Layout::Finders => (
+                        (x as u64 * payload.width as u64 / width as u64) as u32,
+                        (y as u64 * payload.height as u64 / height as u64) as u32,
+                    ),
+                }
+            }
+        };
+        let image_width = self.dimensions.0;
+        let in_grid = |x: u32, y: u32| {
+            if x >= origin.0 && x < end.0 && y >= origin.1 && y < end.1 {
+                Some((x - origin.0, y - origin.1))
+            } else {
+                None
+            }
+        };
+
+        // The average color under each superpixel, for flat baking.
+        let (cells_x, cells_y) = cell(width - 1, height - 1);
+        let cells_x = cells_x as usize + 1;
+        let mut averages = Vec::new();
+        if baking.flat {
+            let mut sums = vec![[0u64; 4]; cells_x * (cells_y as usize + 1)];
+            for (i, pixel) in self.pixels().iter().enumerate() {
+                let (x, y) = match in_grid(i as u32 % image_width, i as u32 / image_width) {
+                    Some((x, y)) => cell(x, y),
+                    None => continue,
+                };
+                let sum = &mut sums[y as usize * cells_x + x as usize];
+                sum[0] += pixel.r as u64;
+                sum[1] += pixel.g as u64;
+                sum[2] += pixel.b as u64;
+                sum[3] += 1;
+            }
+            averages = sums
+                .into_iter()
+                .map(|[r, g, b, count]| Pixel {
+                    r: (r / count.max(1)) as u8,
+                    g: (g / count.max(1)) as u8,
+                    b: (b / count.max(1)) as u8,
+                    a: u8::MAX,
+                })
+                .collect();
+        }
+
+        // Error still to be diffused onto this row and the next.
+        let diffused = baking.rounding == Rounding::Diffused;
+        let row_len = image_width as usize;
+        let mut errors = vec![[0.0f32; 3]; if diffused { row_len * 2 } else { 0 }];
+
+        let modulus = 1u32 << payload.density.bits();
+        let white = Pixel {
+            r: u8::MAX,
+            g: u8::MAX,
+            b: u8::MAX,
+            a: u8::MAX,
+        };
+
+        // Bake a single pixel, with the error diffused onto it so far.  The color it was aiming
+        // for is returned when there is error to diffuse onto its neighbours.
+        let bake = |column: u32, row: u32, pixel: &mut Pixel, error: [f32; 3]| {
+            if column < left || column >= right || row < top || row >= bottom {
+                return None;
+            }
+            let (x, y) = match in_grid(column, row) {
+                Some((x, y)) => cell(x, y),
+                None => {
+                    *pixel = white;
+                    return None;
+                }
+            };
+            if baking.flat {
+                *pixel = Pixel {
+                    a: pixel.a,
+                    ..averages[y as usize * cells_x + x as usize]
+                };
+            }
+
+            let superpixel = if x < payload.width && y < payload.height {
+                payload.get_superpixel(x as usize, y as usize)
+            } else {
+                &Superpixel::Value(((x + y) % modulus) as u16)
+            };
             match superpixel {
                 Superpixel::Black => {
                     *pixel = Pixel {
@@ -400,123 +1303,507 @@ impl Image {
                         g: u8::MIN,
                         b: u8::MIN,
                         a: u8::MAX,
-                    }
+                    };
+                    None
                 }
                 Superpixel::White => {
-                    *pixel = Pixel {
-                        r: u8::MAX,
-                        g: u8::MAX,
-                        b: u8::MAX,
-                        a: u8::MAX,
-                    }
+                    *pixel = white;
+                    None
                 }
                 Superpixel::Value(value) => {
-                    *pixel = pixel.with_value(*value);
+                    let mut target = [pixel.r as f32, pixel.g as f32, pixel.b as f32];
+                    if diffused {
+                        // Error past the darkest or lightest value this superpixel can take is
+                        // dropped, or it would pile up where the image is already as dark or as
+                        // light as the value lets it go.
+                        let density = payload.density;
+                        let darkest = pixel.with_value_near(*value, density, [0.0; 3]);
+                        let lightest = pixel.with_value_near(*value, density, [255.0; 3]);
+                        let range = [
+                            (darkest.r, lightest.r),
+                            (darkest.g, lightest.g),
+                            (darkest.b, lightest.b),
+                        ];
+                        for (channel, &(darkest, lightest)) in range.iter().enumerate() {
+                            target[channel] = (target[channel] + error[channel])
+                                .clamp(darkest as f32, lightest as f32);
+                        }
+                    }
+                    *pixel = match baking.rounding {
+                        Rounding::Center => pixel.with_value_at(*value, payload.density),
+                        _ => pixel.with_value_near(*value, payload.density, target),
+                    };
+                    if diffused {
+                        Some(target)
+                    } else {
+                        None
+                    }
                 }
             }
-        }
-    }
-
-    /// Uses the target to determine width of superpixels
-    pub fn superpixel_width(&self) -> Result<u32, Error> {
-        let mut first_white = false;
+        };
 
-        // Only take the first row
-        for (i, pixel) in self
-            .pixels
-            .iter()
-            .copied()
-            .enumerate()
-            .take(self.dimensions.0 as usize)
+        // Without error to carry from one pixel to the next, every row can be baked at once.
+        #[cfg(feature = "parallel")]
         {
-            if !first_white {
-                // looking for the first white pixel
-                if pixel.r > 235 && pixel.b > 235 && pixel.g > 235 {
-                    first_white = true;
-                }
+            if !diffused {
+                use rayon::prelude::*;
+                self.pixels
+                    .as_mut()
+                    .par_chunks_mut(row_len)
+                    .enumerate()
+                    .for_each(|(row, pixels)| {
+                        for (column, pixel) in pixels.iter_mut().enumerate() {
+                            bake(column as u32, row as u32, pixel, [0.0; 3]);
+                        }
+                    });
+                return Ok(());
+            }
+        }
+
+        for (i, pixel) in self.pixels.as_mut().iter_mut().enumerate() {
+            let (column, row) = (i as u32 % image_width, i as u32 / image_width);
+            if diffused && column == 0 {
+                errors.copy_within(row_len.., 0);
+                errors[row_len..].fill([0.0; 3]);
+            }
+            let error = if diffused {
+                errors[column as usize]
             } else {
-                // looking for the first black pixel after the first white
-                if pixel.r < 20 && pixel.b < 20 && pixel.g < 20 {
-                    return Ok(i as u32 / 2);
+                [0.0; 3]
+            };
+            let target = match bake(column, row, pixel, error) {
+                Some(target) => target,
+                None => continue,
+            };
+
+            let x = column as usize;
+            let baked = [pixel.r as f32, pixel.g as f32, pixel.b as f32];
+            for &(dx, dy, weight) in &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+                let x = match (x as isize + dx) as usize {
+                    x if x < row_len => x,
+                    _ => continue,
+                };
+                let spread = &mut errors[dy * row_len + x];
+                for channel in 0..3 {
+                    spread[channel] += (target[channel] - baked[channel]) * weight / 16.0;
                 }
             }
         }
+        Ok(())
+    }
+}
 
-        Err(Error::NoTargetFound)
+impl<P: AsRef<[Pixel]>> Image<P> {
+    /// Uses the target to determine width of superpixels
+    pub fn superpixel_width(&self) -> Result<u32, Error> {
+        let reading = self.locate_payload()?;
+        Ok(reading.grid.superpixel_size().0.round() as u32)
     }
 
     /// Uses the target to determine height of superpixels
     pub fn superpixel_height(&self) -> Result<u32, Error> {
-        let mut first_white = false;
+        let reading = self.locate_payload()?;
+        Ok(reading.grid.superpixel_size().1.round() as u32)
+    }
 
-        // Only take the first column
-        for (i, pixel) in self
-            .pixels
-            .iter()
-            .step_by(self.dimensions.0 as usize)
-            .copied()
-            .enumerate()
-        {
-            if !first_white {
-                // looking for the first white pixel
-                if pixel.r > 235 && pixel.b > 235 && pixel.g > 235 {
-                    first_white = true;
+    /// Read a single superpixel of a grid at the given density, by majority vote of the pixels in
+    /// it, along with how clear that majority was.  The votes are counted in the given buffer.
+    fn read_superpixel(
+        &self,
+        grid: &Grid,
+        (x, y): (u32, u32),
+        density: Density,
+        calibration: &Calibration,
+        votes: &mut Votes,
+    ) -> (Superpixel, Confidence) {
+        let samples = self.samples(grid, x, y);
+        let position = (x as f32, y as f32);
+        self.sample_superpixel(grid, position, density, samples, calibration, votes)
+    }
+
+    /// One sample for roughly every pixel a superpixel covers along each of its edges.
+    fn samples(&self, grid: &Grid, x: u32, y: u32) -> (u32, u32) {
+        let (x, y) = (x as f32, y as f32);
+        let span = 1.0 - grid.margin() * 2.0;
+        let corner = grid.to_image(x, y);
+        let edge = |(end_x, end_y): (f32, f32)| {
+            (((end_x - corner.0).hypot(end_y - corner.1) * span).round() as u32).max(1)
+        };
+        (
+            edge(grid.to_image(x + 1.0, y)),
+            edge(grid.to_image(x, y + 1.0)),
+        )
+    }
+
+    /// The pixels a single superpixel of a grid is read from.
+    pub(crate) fn sample_pixels(&self, grid: &Grid, x: u32, y: u32) -> Vec<Pixel> {
+        let (samples_x, samples_y) = self.samples(grid, x, y);
+        let mut pixels = Vec::with_capacity(samples_x as usize * samples_y as usize);
+        self.for_each_sample(
+            grid,
+            (x as f32, y as f32),
+            (samples_x, samples_y),
+            |pixel| pixels.push(pixel),
+        );
+        pixels
+    }
+
+    /// Visit the pixels under a single superpixel of a grid, with the given number of samples
+    /// along each of its edges.  Samples that land outside the image are skipped.
+    fn for_each_sample<F: FnMut(Pixel)>(
+        &self,
+        grid: &Grid,
+        (x, y): (f32, f32),
+        (samples_x, samples_y): (u32, u32),
+        mut visit: F,
+    ) {
+        let margin = grid.margin();
+        let span = 1.0 - margin * 2.0;
+
+        for sub_y in 0..samples_y {
+            for sub_x in 0..samples_x {
+                let (pixel_x, pixel_y) = grid.to_image(
+                    x + margin + span * (sub_x as f32 + 0.5) / samples_x as f32,
+                    y + margin + span * (sub_y as f32 + 0.5) / samples_y as f32,
+                );
+                let (pixel_x, pixel_y) = (pixel_x.floor(), pixel_y.floor());
+                if pixel_x < 0.0
+                    || pixel_y < 0.0
+                    || pixel_x >= self.dimensions.0 as f32
+                    || pixel_y >= self.dimensions.1 as f32
+                {
+                    continue;
                 }
-            } else {
-                // looking for the first black pixel after the first white
-                if pixel.r < 20 && pixel.b < 20 && pixel.g < 20 {
-                    return Ok(i as u32 / 2);
+                let pixel_offset = pixel_y as usize * self.dimensions.0 as usize + pixel_x as usize;
+                visit(self.pixels()[pixel_offset]);
+            }
+        }
+    }
+
+    /// Vote on a single superpixel of a grid with the given number of samples along each of its
+    /// edges, calibrating every pixel before reading it.
+    fn sample_superpixel(
+        &self,
+        grid: &Grid,
+        position: (f32, f32),
+        density: Density,
+        samples: (u32, u32),
+        calibration: &Calibration,
+        votes: &mut Votes,
+    ) -> (Superpixel, Confidence) {
+        votes.clear();
+        self.for_each_sample(grid, position, samples, |pixel| {
+            votes.add(calibration.apply(pixel).value_at(density))
+        });
+
+        // Final value determined by max membership
+        votes.tally()
+    }
+
+    /// Read the payload from the given grid, taking the largest square that fits in the image,
+    /// along with how sure the read was of each superpixel.
+    ///
+    /// The grid's black and white levels are measured from its layout's patterns first, and
+    /// every pixel is calibrated to them before it is read.
+    pub fn read_grid_with_confidence(
+        &self,
+        grid: &Grid,
+    ) -> Result<(Payload, ConfidenceGrid), Error> {
+        self.read_calibrated_grid(grid, &self.calibrate(grid))
+    }
+
+    /// Read the payload from the given grid with the given calibration.
+    fn read_calibrated_grid(
+        &self,
+        grid: &Grid,
+        calibration: &Calibration,
+    ) -> Result<(Payload, ConfidenceGrid), Error> {
+        let grid_size = grid.columns().min(grid.rows());
+
+        // A view of the pixels that can be shared between threads, whatever they are held in.
+        let image = Image {
+            dimensions: self.dimensions,
+            pixels: self.pixels(),
+        };
+        let read = |density, (columns, rows): (u32, u32)| {
+            let read_row = |y| {
+                let mut votes = Votes::default();
+                (0..columns)
+                    .map(|x| image.read_superpixel(grid, (x, y), density, calibration, &mut votes))
+                    .collect::<Vec<_>>()
+            };
+            #[cfg(feature = "parallel")]
+            let read: Vec<_> = {
+                use rayon::prelude::*;
+                (0..rows).into_par_iter().map(read_row).collect()
+            };
+            #[cfg(not(feature = "parallel"))]
+            let read: Vec<_> = (0..rows).map(read_row).collect();
+            let (superpixels, confidences): (Vec<_>, Vec<_>) = read.into_iter().flatten().unzip();
+            Payload::from_raw_grid((columns, rows), superpixels)
+                .map(|payload| (payload, ConfidenceGrid::new((columns, rows), confidences)))
+        };
+
+        // Black and white read the same at every density, so the density marker can be read
+        // before the density is known.
+        let square = (grid_size, grid_size);
+        let (payload, confidence) = read(Density::default(), square)?;
+        let (payload, confidence) = if payload.density == Density::default() {
+            (payload, confidence)
+        } else {
+            read(payload.density, square)?
+        };
+
+        // A grid that isn't square has its header in the square at its corner, giving the rest.
+        match payload.header().map(|header| header.grid) {
+            Ok(Some((columns, rows)))
+                if columns as u32 <= grid.columns() && rows as u32 <= grid.rows() =>
+            {
+                read(payload.density, (columns as u32, rows as u32))
+            }
+            _ => Ok((payload, confidence)),
+        }
+    }
+
+    /// Read the payload from the given grid, taking the largest square that fits in the image.
+    pub fn read_grid(&self, grid: &Grid) -> Result<Payload, Error> {
+        self.read_grid_with_confidence(grid)
+            .map(|(payload, _)| payload)
+    }
+
+    /// Read the payload on a candidate grid.  If its header can be read, the grid is refined,
+    /// and the columns and rows of the encoded payload are returned along with the reading.
+    fn read_candidate(&self, grid: Grid) -> Result<(Reading, Option<(u32, u32)>), Error> {
+        let calibration = self.calibrate(&grid);
+        let (payload, confidence) = self.read_calibrated_grid(&grid, &calibration)?;
+        let reading = Reading {
+            grid,
+            payload,
+            confidence,
+            calibration,
+        };
+        let dimensions = match reading.payload.encoded_dimensions() {
+            Ok(dimensions) => dimensions,
+            Err(_) => return Ok((reading, None)),
+        };
+
+        // Refining is too slow to do for every candidate, but the header sits close enough to
+        // the corner to be read without it.
+        let grid = self.refine(&grid, dimensions, &calibration);
+        if grid != reading.grid {
+            let calibration = self.calibrate(&grid);
+            if let Ok((payload, confidence)) = self.read_calibrated_grid(&grid, &calibration) {
+                if let Ok(dimensions) = payload.encoded_dimensions() {
+                    let reading = Reading {
+                        grid,
+                        payload,
+                        confidence,
+                        calibration,
+                    };
+                    return Ok((reading, Some(dimensions)));
                 }
             }
         }
+        Ok((reading, Some(dimensions)))
+    }
 
-        Err(Error::NoTargetFound)
+    /// Candidate targets in a copy of this image stretched to reach black and white, for when
+    /// the image is too dim or washed out for any to be found as it is.  Grids line up the same
+    /// in both, so they are read from this image, calibrated to their own targets.
+    fn stretched_grid_candidates(&self) -> Vec<Grid> {
+        self.stretched()
+            .map_or_else(Vec::new, |stretched| stretched.grid_candidates())
     }
 
-    /// Read a payload from this image.
-    pub fn read_payload(&self) -> Result<Payload, Error> {
-        let superpixel_width = self.superpixel_width()?;
-        let superpixel_height = self.superpixel_height()?;
-
-        let horizontal_superpixels = self.dimensions.0 / superpixel_width;
-        let vertical_superpixels = self.dimensions.1 / superpixel_height;
-
-        let grid_size = horizontal_superpixels.min(vertical_superpixels);
-
-        let mut superpixels = Vec::new();
-
-        for y in 0..grid_size {
-            for x in 0..grid_size {
-                // All the pixels in this superpixel
-                let mut pixels = Vec::new();
-                for sub_y in 0..superpixel_height {
-                    for sub_x in 0..superpixel_width {
-                        // Total pixel offsets
-                        let x_offset = x * superpixel_width + sub_x;
-                        let y_offset = y * superpixel_height + sub_y;
-                        let pixel_offset =
-                            y_offset as usize * self.dimensions.0 as usize + x_offset as usize;
-                        pixels.push(self.pixels[pixel_offset].value());
-                    }
+    /// Find the grid holding a payload, along with that payload.
+    ///
+    /// Every candidate target is tried, and the first one whose header can be read wins.  If none
+    /// works out, targets are searched for again with the image stretched to reach black and
+    /// white.  Finder patterns are only searched for after that, because that is much slower.  If
+    /// no candidate has a readable header, the first one is returned anyway, so that the caller
+    /// gets a meaningful error when decoding it.
+    fn locate_payload(&self) -> Result<Reading, Error> {
+        let searches: [Search<P>; 3] = [
+            Image::grid_candidates,
+            Image::stretched_grid_candidates,
+            Image::finder_candidates,
+        ];
+        let mut first = None;
+        for search in &searches {
+            for grid in search(self) {
+                let (reading, dimensions) = match self.read_candidate(grid) {
+                    Ok(read) => read,
+                    Err(_) => continue,
+                };
+                if dimensions.is_some() {
+                    return Ok(reading);
+                }
+                if first.is_none() {
+                    first = Some(reading);
                 }
+            }
+        }
+        first.ok_or(Error::NoTargetFound)
+    }
 
-                // Collect these into counted groups
-                let counted = pixels.into_iter().fold(HashMap::new(), |mut acc, pixel| {
-                    *acc.entry(pixel).or_insert(0) += 1;
-                    acc
-                });
+    /// Read every payload baked into this image, such as one per panel of a poster, in reading
+    /// order: by the top edge of each, then by the left edge.
+    ///
+    /// Every candidate target whose header can be read is kept, apart from ones that fall inside
+    /// a payload already found.  As with a single payload, the image is only stretched, and then
+    /// finder patterns only searched for, if no target works out, and then only the first payload
+    /// they lead to is kept, because a set of finder patterns can't be told apart from another
+    /// one.
+    pub fn read_payloads(&self) -> Vec<Reading> {
+        let mut found: Vec<(Reading, (u32, u32))> = Vec::new();
+        let searches: [Search<P>; 2] = [Image::grid_candidates, Image::stretched_grid_candidates];
+        for search in &searches {
+            if !found.is_empty() {
+                break;
+            }
+            for grid in search(self) {
+                let corner = grid.to_image(0.5, 0.5);
+                if found
+                    .iter()
+                    .any(|(reading, dimensions)| reading.grid.covers(corner, *dimensions))
+                {
+                    continue;
+                }
+                if let Ok((reading, Some(dimensions))) = self.read_candidate(grid) {
+                    found.push((reading, dimensions));
+                }
+            }
+        }
 
-                // Final value determined by max membership
-                let superpixel = counted
+        if found.is_empty() {
+            found.extend(
+                self.finder_candidates()
                     .into_iter()
-                    .max_by_key(|(_, count)| *count)
-                    .unwrap()
-                    .0;
-                superpixels.push(superpixel);
+                    .filter_map(|grid| match self.read_candidate(grid) {
+                        Ok((reading, Some(dimensions))) => Some((reading, dimensions)),
+                        _ => None,
+                    })
+                    .take(1),
+            );
+        }
+
+        // Sorted by the top edge, then the left edge, of the box around each payload.
+        let top_left = |reading: &Reading| {
+            let corners = reading.corners();
+            let top = corners
+                .iter()
+                .map(|&(_, y)| y)
+                .fold(f32::INFINITY, f32::min);
+            let left = corners
+                .iter()
+                .map(|&(x, _)| x)
+                .fold(f32::INFINITY, f32::min);
+            (top, left)
+        };
+        let mut readings: Vec<Reading> = found.into_iter().map(|(reading, _)| reading).collect();
+        readings.sort_by(|a, b| {
+            top_left(a)
+                .partial_cmp(&top_left(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        readings
+    }
+
+    /// Find where the payload sits in this image, in any orientation.
+    pub fn locate(&self) -> Result<Grid, Error> {
+        self.locate_payload().map(|reading| reading.grid)
+    }
+
+    /// Read a payload from this image.
+    ///
+    /// If the first payload found is too damaged to decode, any other copies of it, such as
+    /// from a tiled baking, are merged with it.
+    pub fn read_payload(&self) -> Result<Payload, Error> {
+        self.read_payload_with_confidence()
+            .map(|reading| reading.payload)
+    }
+
+    /// Read every copy of a payload in this image and merge them, superpixel by superpixel.
+    ///
+    /// Copies are payloads whose headers match exactly, checksum included, and the payload with
+    /// the most copies is the one merged.  Each copy votes for what it read with how sure it was,
+    /// so a superpixel misread in one copy is outvoted by the others, and a copy cut short by
+    /// the edge of the image still votes for what it has.
+    pub fn read_merged_payload(&self) -> Result<Payload, Error> {
+        let copies: Vec<(Reading, Parsed)> = self
+            .read_payloads()
+            .into_iter()
+            .filter_map(|reading| {
+                let parsed = reading.payload.parse_header().ok()?;
+                Some((reading, parsed))
+            })
+            .collect();
+        let same = |a: &(Reading, Parsed), b: &(Reading, Parsed)| {
+            a.1 == b.1
+                && a.0.payload.layout == b.0.payload.layout
+                && a.0.payload.density == b.0.payload.density
+        };
+        // The first in reading order wins ties.
+        let mut best: Option<(usize, &(Reading, Parsed))> = None;
+        for copy in &copies {
+            let count = copies.iter().filter(|other| same(copy, other)).count();
+            if best.is_none_or(|(most, _)| count > most) {
+                best = Some((count, copy));
+            }
+        }
+        let (_, best) = best.ok_or(Error::NoTargetFound)?;
+        let copies: Vec<&Reading> = copies
+            .iter()
+            .filter(|&copy| same(best, copy))
+            .map(|(reading, _)| reading)
+            .collect();
+
+        let (layout, density) = (best.0.payload.layout, best.0.payload.density);
+        let (width, height) = Payload::dimensions_for(layout, density, &best.1);
+        let mut merged = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut votes: Vec<(Superpixel, f32)> = Vec::new();
+                for reading in &copies {
+                    let read_width = reading.payload.width;
+                    if x >= read_width || y >= reading.payload.height {
+                        continue;
+                    }
+                    let superpixel = reading.payload.data[(y * read_width + x) as usize];
+                    let share = reading.confidence.get(x, y).map_or(0.0, |c| c.share);
+                    match votes.iter_mut().find(|(voted, _)| *voted == superpixel) {
+                        Some((_, total)) => *total += share,
+                        None => votes.push((superpixel, share)),
+                    }
+                }
+                merged.push(
+                    votes
+                        .into_iter()
+                        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+                        .map_or(Superpixel::Black, |(superpixel, _)| superpixel),
+                );
             }
         }
+        Payload::from_raw_grid((width, height), merged)
+    }
 
-        Ok(Payload::from_raw(grid_size as u8, superpixels)?)
+    /// Read a payload from this image, along with where it was found and how sure the read was of
+    /// each superpixel, to show which parts of an image are close to failing.
+    ///
+    /// As with [`Image::read_payload`], a payload too damaged to decode is merged with any other
+    /// copies of it, and the merged payload is given along with where the first was found.
+    pub fn read_payload_with_confidence(&self) -> Result<Reading, Error> {
+        let reading = self.locate_payload()?;
+        if reading.payload.decode().is_ok() {
+            return Ok(reading);
+        }
+        match self.read_merged_payload() {
+            Ok(merged) if merged.decode().is_ok() => Ok(Reading {
+                payload: merged,
+                ..reading
+            }),
+            _ => Ok(reading),
+        }
     }
 }
 
@@ -527,30 +1814,183 @@ mod test {
 
     #[test]
     fn affinity_rounding() {
-        assert_eq!(round_to_affinity(0, 0), 0);
-        assert_eq!(round_to_affinity(1, 0), 24);
-        assert_eq!(round_to_affinity(2, 0), 40);
-        assert_eq!(round_to_affinity(3, 0), 56);
+        assert_eq!(round_to_affinity(Density::Standard, 0, 0), 0);
+        assert_eq!(round_to_affinity(Density::Standard, 1, 0), 24);
+        assert_eq!(round_to_affinity(Density::Standard, 2, 0), 40);
+        assert_eq!(round_to_affinity(Density::Standard, 3, 0), 56);
+
+        assert_eq!(round_to_affinity(Density::Standard, 0, 64), 72);
+        assert_eq!(round_to_affinity(Density::Standard, 1, 64), 88);
+        assert_eq!(round_to_affinity(Density::Standard, 2, 64), 40);
+        assert_eq!(round_to_affinity(Density::Standard, 3, 64), 56);
+
+        assert_eq!(round_to_affinity(Density::Standard, 0, 128), 136);
+        assert_eq!(round_to_affinity(Density::Standard, 1, 128), 152);
+        assert_eq!(round_to_affinity(Density::Standard, 2, 128), 104);
+        assert_eq!(round_to_affinity(Density::Standard, 3, 128), 120);
+
+        assert_eq!(round_to_affinity(Density::Standard, 0, 192), 200);
+        assert_eq!(round_to_affinity(Density::Standard, 1, 192), 216);
+        assert_eq!(round_to_affinity(Density::Standard, 2, 192), 168);
+        assert_eq!(round_to_affinity(Density::Standard, 3, 192), 184);
+
+        assert_eq!(round_to_affinity(Density::Standard, 0, 255), 200);
+        assert_eq!(round_to_affinity(Density::Standard, 1, 255), 216);
+        assert_eq!(round_to_affinity(Density::Standard, 2, 255), 232);
+        assert_eq!(round_to_affinity(Density::Standard, 3, 255), 255);
+    }
 
-        assert_eq!(round_to_affinity(0, 64), 72);
-        assert_eq!(round_to_affinity(1, 64), 88);
-        assert_eq!(round_to_affinity(2, 64), 40);
-        assert_eq!(round_to_affinity(3, 64), 56);
+    #[test]
+    fn pixel_values_at_every_density() {
+        let mut rng = rand::thread_rng();
+        for &density in &[
+            Density::Robust,
+            Density::Standard,
+            Density::Dense,
+            Density::Densest,
+            Density::Grayscale,
+        ] {
+            for value in 0..1 << density.bits() {
+                let pixel = Pixel {
+                    r: rng.gen(),
+                    g: rng.gen(),
+                    b: rng.gen(),
+                    a: rng.gen(),
+                };
+                let read = pixel.with_value_at(value, density).value_at(density);
+                assert_eq!(density.value(read), value, "{:?} {:?}", density, pixel);
 
-        assert_eq!(round_to_affinity(0, 128), 136);
-        assert_eq!(round_to_affinity(1, 128), 152);
-        assert_eq!(round_to_affinity(2, 128), 104);
-        assert_eq!(round_to_affinity(3, 128), 120);
+                let target = [rng.gen_range(-40.0, 300.0); 3];
+                let near = pixel.with_value_near(value, density, target);
+                assert_eq!(density.value(near.value_at(density)), value, "{:?}", near);
+            }
+        }
+    }
 
-        assert_eq!(round_to_affinity(0, 192), 200);
-        assert_eq!(round_to_affinity(1, 192), 216);
-        assert_eq!(round_to_affinity(2, 192), 168);
-        assert_eq!(round_to_affinity(3, 192), 184);
+    #[test]
+    fn density_roundtrip() {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..500).map(|_| rng.gen()).collect();
+        let mut widths = Vec::new();
+        for &density in &[
+            Density::Robust,
+            Density::Standard,
+            Density::Dense,
+            Density::Densest,
+        ] {
+            let payload = Payload::with_encoding(
+                &data,
+                Encoding {
+                    density,
+                    ..Encoding::default()
+                },
+            )
+            .expect("Could not create payload");
+            widths.push(payload.width);
+
+            let mut image = random_image((150, 150));
+            image
+                .bake_payload(&payload)
+                .expect("Could not bake payload");
+            let read = image.read_payload().expect("Could not read payload");
+            assert_eq!(read.density(), density);
+            assert_eq!(read.data().expect("Could not read data"), data);
+        }
 
-        assert_eq!(round_to_affinity(0, 255), 200);
-        assert_eq!(round_to_affinity(1, 255), 216);
-        assert_eq!(round_to_affinity(2, 255), 232);
-        assert_eq!(round_to_affinity(3, 255), 255);
+        // Denser payloads need fewer superpixels.
+        assert!(widths.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    /// Average every 4x4 block of an image, as it looks from a distance.
+    fn shrink(image: &Image) -> Image {
+        let (width, height) = (image.dimensions.0 / 4, image.dimensions.1 / 4);
+        let pixels: Vec<Pixel> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width * 4, i / width * 4);
+                let mut sum = [0u32; 3];
+                for dy in 0..4 {
+                    for dx in 0..4 {
+                        let pixel = image.pixels[((y + dy) * image.dimensions.0 + x + dx) as usize];
+                        sum[0] += pixel.r as u32;
+                        sum[1] += pixel.g as u32;
+                        sum[2] += pixel.b as u32;
+                    }
+                }
+                Pixel {
+                    r: (sum[0] / 16) as u8,
+                    g: (sum[1] / 16) as u8,
+                    b: (sum[2] / 16) as u8,
+                    a: u8::MAX,
+                }
+            })
+            .collect();
+        Image::new((width, height), pixels)
+    }
+
+    #[test]
+    fn rounding_keeps_margin_and_improves_quality() {
+        let data: Vec<u8> = (0..200).map(|i| (i * 7) as u8).collect();
+        let payload = Payload::new(&data).expect("Could not create payload");
+        let dimensions = (240, 240);
+        let original = Image::new(
+            dimensions,
+            (0..dimensions.0 * dimensions.1)
+                .map(|i| {
+                    let (x, y) = (i % dimensions.0, i / dimensions.0);
+                    Pixel {
+                        r: x as u8,
+                        g: y as u8,
+                        b: ((x + y) / 2) as u8,
+                        a: u8::MAX,
+                    }
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let mut quality = Vec::new();
+        for &rounding in &[Rounding::Center, Rounding::Nearest, Rounding::Diffused] {
+            let mut image = original.clone();
+            let baking = Baking {
+                rounding,
+                ..Baking::default()
+            };
+            image
+                .bake_payload_with(&payload, baking)
+                .expect("Could not bake payload");
+            let read = image
+                .read_payload()
+                .expect("Could not read payload")
+                .data()
+                .expect("Could not read data");
+            assert_eq!(data, read, "{:?}", rounding);
+
+            // Every value keeps at least a quarter band from the edges of its band.
+            for pixel in &image.pixels {
+                for &channel in &[pixel.r, pixel.g, pixel.b] {
+                    let offset = channel as u16 % Density::Standard.band();
+                    if channel != 0 && channel != u8::MAX {
+                        assert!(offset >= 4 && offset <= 11, "{:?}", pixel);
+                    }
+                }
+            }
+            quality.push((
+                original.psnr(&image).unwrap(),
+                original.ssim(&image).unwrap(),
+                shrink(&original).psnr(&shrink(&image)).unwrap(),
+            ));
+        }
+        // Nearest is closer pixel for pixel, and diffused is closer in color from a distance.
+        assert!(quality[1].0 > quality[0].0, "{:?}", quality);
+        assert!(quality[1].1 > quality[0].1, "{:?}", quality);
+        assert!(quality[2].2 > quality[0].2, "{:?}", quality);
+        assert!(quality[2].2 > quality[1].2, "{:?}", quality);
+
+        assert_eq!(original.psnr(&original), Ok(f64::INFINITY));
+        assert!((original.ssim(&original).unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(
+            original.psnr(&random_image((10, 10))),
+            Err(Error::InvalidDimensions)
+        );
     }
 
     #[test]
@@ -573,9 +2013,11 @@ mod test {
         );
 
         let data: Vec<u8> = (0..1000).map(|_| rng.gen()).collect();
-        let payload = Payload::new(&data);
+        let payload = Payload::new(&data).expect("Could not create payload");
 
-        origin_image.bake_payload(&payload);
+        origin_image
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
         let read_data = origin_image
             .read_payload()
             .expect("Could not read payload")
@@ -584,11 +2026,1022 @@ mod test {
         assert_eq!(data, read_data);
     }
 
+    /// Run an image through a transform of the image crate's buffers.
+    fn transform<F: FnOnce(image::RgbaImage) -> image::RgbaImage>(
+        source: &Image,
+        transform: F,
+    ) -> Image {
+        let (width, height) = source.dimensions;
+        let mut buffer = image::RgbaImage::new(width, height);
+        for (out_pixel, pixel) in buffer.pixels_mut().zip(source.pixels.iter()) {
+            *out_pixel = image::Rgba([pixel.r, pixel.g, pixel.b, pixel.a]);
+        }
+        let pixels: Vec<Pixel> = transform(buffer)
+            .pixels()
+            .map(|pixel| Pixel {
+                r: pixel[0],
+                g: pixel[1],
+                b: pixel[2],
+                a: pixel[3],
+            })
+            .collect();
+        Image::new((width, height), pixels)
+    }
+
+    #[test]
+    fn grayscale_and_palette_roundtrip() {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..100).map(|_| rng.gen()).collect();
+        let payload = Payload::with_encoding(
+            &data,
+            Encoding {
+                density: Density::Grayscale,
+                ..Encoding::default()
+            },
+        )
+        .expect("Could not create payload");
+
+        let mut image = random_image((150, 150));
+        for pixel in &mut image.pixels {
+            pixel.a = u8::MAX;
+        }
+        image
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
+
+        let grayscale = transform(&image, |buffer| {
+            image::DynamicImage::ImageLuma8(image::imageops::grayscale(&buffer)).into_rgba()
+        });
+        let averaged = transform(&image, |mut buffer| {
+            for pixel in buffer.pixels_mut() {
+                let gray = ((pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3) as u8;
+                *pixel = image::Rgba([gray, gray, gray, pixel[3]]);
+            }
+            buffer
+        });
+        let gif = transform(&image, |buffer| {
+            let mut encoded = Vec::new();
+            image::gif::Encoder::new(&mut encoded)
+                .encode(
+                    &buffer,
+                    buffer.width(),
+                    buffer.height(),
+                    image::ColorType::Rgba8,
+                )
+                .expect("Could not encode gif");
+            image::load_from_memory_with_format(&encoded, image::ImageFormat::Gif)
+                .expect("Could not decode gif")
+                .into_rgba()
+        });
+
+        for transformed in &[grayscale, averaged, gif] {
+            let read = transformed.read_payload().expect("Could not read payload");
+            assert_eq!(read.density(), Density::Grayscale);
+            assert_eq!(read.data().expect("Could not read data"), data);
+        }
+    }
+
+    fn random_image(dimensions: (u32, u32)) -> Image {
+        let mut rng = rand::thread_rng();
+        Image::new(
+            dimensions,
+            std::iter::from_fn(|| {
+                Some(Pixel {
+                    r: rng.gen(),
+                    g: rng.gen(),
+                    b: rng.gen(),
+                    a: rng.gen(),
+                })
+            })
+            .take(dimensions.0 as usize * dimensions.1 as usize)
+            .collect::<Vec<Pixel>>(),
+        )
+    }
+
+    /// Build a new image by looking up each of its pixels in the source image.
+    fn remap<F: Fn(u32, u32) -> Option<(u32, u32)>>(
+        source: &Image,
+        dimensions: (u32, u32),
+        lookup: F,
+    ) -> Image {
+        let mut pixels = Vec::with_capacity(dimensions.0 as usize * dimensions.1 as usize);
+        for y in 0..dimensions.1 {
+            for x in 0..dimensions.0 {
+                pixels.push(match lookup(x, y) {
+                    Some((x, y)) => source.pixels[(y * source.dimensions.0 + x) as usize],
+                    None => Pixel {
+                        r: 10,
+                        g: 10,
+                        b: 10,
+                        a: 255,
+                    },
+                });
+            }
+        }
+        Image::new(dimensions, pixels)
+    }
+
+    #[test]
+    fn locate_in_all_orientations() {
+        let mut rng = rand::thread_rng();
+
+        let mut image = random_image((90, 70));
+        let data: Vec<u8> = (0..300).map(|_| rng.gen()).collect();
+        image
+            .bake_payload(&Payload::new(&data).expect("Could not create payload"))
+            .expect("Could not bake payload");
+
+        // Surround it with a dark border, like a screenshot with window chrome.
+        let (width, height) = image.dimensions();
+        let framed = remap(&image, (width + 31, height + 17), |x, y| {
+            if x >= 13 && y >= 9 && x - 13 < width && y - 9 < height {
+                Some((x - 13, y - 9))
+            } else {
+                None
+            }
+        });
+        let (width, height) = framed.dimensions();
+
+        for &transpose in &[false, true] {
+            for &flip_x in &[false, true] {
+                for &flip_y in &[false, true] {
+                    let dimensions = if transpose {
+                        (height, width)
+                    } else {
+                        (width, height)
+                    };
+                    let transformed = remap(&framed, dimensions, |x, y| {
+                        let x = if flip_x { dimensions.0 - 1 - x } else { x };
+                        let y = if flip_y { dimensions.1 - 1 - y } else { y };
+                        Some(if transpose { (y, x) } else { (x, y) })
+                    });
+                    let read_data = transformed
+                        .read_payload()
+                        .expect("Could not read payload")
+                        .data()
+                        .expect("Could not read data");
+                    assert_eq!(
+                        data, read_data,
+                        "transpose: {}, flip x: {}, flip y: {}",
+                        transpose, flip_x, flip_y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn read_in_perspective() {
+        let mut rng = rand::thread_rng();
+
+        let mut image = random_image((250, 250));
+        let data: Vec<u8> = (0..200).map(|_| rng.gen()).collect();
+        let payload = Payload::with_encoding(
+            &data,
+            Encoding {
+                layout: Layout::Finders,
+                ..Encoding::default()
+            },
+        )
+        .expect("Could not create payload");
+        image
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
+
+        let corners = [(0.0, 0.0), (250.0, 0.0), (0.0, 250.0), (250.0, 250.0)];
+        let views = [
+            // Seen from below and to the left
+            [(30.0, 20.0), (280.0, 45.0), (15.0, 270.0), (300.0, 290.0)],
+            // Turned by about 30 degrees
+            [(110.0, 10.0), (283.2, 110.0), (10.0, 183.2), (183.2, 283.2)],
+            // Mirrored and skewed
+            [(290.0, 30.0), (20.0, 10.0), (300.0, 260.0), (40.0, 300.0)],
+        ];
+        for view in &views {
+            let to_source =
+                homography::Homography::from_points(view, &corners).expect("Bad test view");
+            let photo = remap(&image, (330, 350), |x, y| {
+                let (x, y) = to_source.apply(x as f64 + 0.5, y as f64 + 0.5);
+                if x >= 0.0 && y >= 0.0 && x < 250.0 && y < 250.0 {
+                    Some((x as u32, y as u32))
+                } else {
+                    None
+                }
+            });
+            let read = photo.read_payload().expect("Could not read payload");
+            assert_eq!(read.layout(), Layout::Finders);
+            assert_eq!(
+                data,
+                read.data().expect("Could not read data"),
+                "{:?}",
+                view
+            );
+        }
+    }
+
+    #[test]
+    fn read_with_shifted_levels() {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..150).map(|_| rng.gen()).collect();
+        let payload = Payload::new(&data).expect("Could not create payload");
+        let mut image = random_image((200, 200));
+        image
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
+
+        // A dim, low contrast capture with a color cast.
+        let levels = [(45.0, 0.7), (30.0, 0.75), (60.0, 0.65)];
+        let shift = |value: u8, (offset, gain): (f32, f32)| (offset + value as f32 * gain) as u8;
+        for pixel in &mut image.pixels {
+            *pixel = Pixel {
+                r: shift(pixel.r, levels[0]),
+                g: shift(pixel.g, levels[1]),
+                b: shift(pixel.b, levels[2]),
+                a: pixel.a,
+            };
+        }
+
+        let reading = image
+            .read_payload_with_confidence()
+            .expect("Could not read payload");
+        assert_eq!(reading.payload.data(), Ok(data));
+        for (i, &(offset, gain)) in levels.iter().enumerate() {
+            assert!((reading.calibration.black[i] - offset).abs() <= 1.0);
+            assert!((reading.calibration.white[i] - (offset + 255.0 * gain)).abs() <= 1.0);
+        }
+
+        // The same grid can't be read without calibrating it.
+        let uncalibrated = image.read_calibrated_grid(&reading.grid, &Calibration::default());
+        assert!(uncalibrated.map_or(true, |(payload, _)| payload.data().is_err()));
+    }
+
+    #[test]
+    fn large_payload_roundtrip() {
+        let mut rng = rand::thread_rng();
+
+        let data: Vec<u8> = (0..70000).map(|_| rng.gen()).collect();
+        let payload = Payload::with_error_correction(&data, ErrorCorrection::Low)
+            .expect("Could not create payload");
+        assert!(payload.width > u8::MAX as u32);
+
+        let dimensions = (payload.width, payload.width);
+        let mut image = Image::new(
+            dimensions,
+            vec![
+                Pixel {
+                    r: 100,
+                    g: 150,
+                    b: 200,
+                    a: 255,
+                };
+                dimensions.0 as usize * dimensions.1 as usize
+            ],
+        );
+        assert_eq!(
+            image.bake_payload(&Payload::new(&data).expect("Could not create payload")),
+            Err(Error::ImageTooSmall)
+        );
+
+        image
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
+        let read_data = image
+            .read_payload()
+            .expect("Could not read payload")
+            .data()
+            .expect("Could not read data");
+        assert_eq!(data, read_data);
+    }
+
+    #[test]
+    fn placed_payload_roundtrip() {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..100).map(|_| rng.gen()).collect();
+        let payload = Payload::new(&data).expect("Could not create payload");
+        let dimensions = (320, 240);
+
+        for &(placement, quiet_zone, block) in &[
+            (
+                Placement::Pixels {
+                    x: 190,
+                    y: 110,
+                    width: 130,
+                    height: 130,
+                },
+                4,
+                1,
+            ),
+            (
+                Placement::Fraction {
+                    x: 0.0,
+                    y: 0.0,
+                    width: 0.5,
+                    height: 0.625,
+                },
+                0,
+                1,
+            ),
+            (
+                Placement::Fraction {
+                    x: 0.25,
+                    y: 0.2,
+                    width: 0.5,
+                    height: 0.6,
+                },
+                3,
+                4,
+            ),
+        ] {
+            let baking = Baking {
+                placement,
+                quiet_zone,
+                block,
+                ..Baking::default()
+            };
+            let original = random_image(dimensions);
+            let mut image = original.clone();
+            image
+                .bake_payload_with(&payload, baking)
+                .expect("Could not bake payload");
+
+            let (left, top, width, height) = placement.rectangle(dimensions).unwrap();
+            for y in 0..dimensions.1 {
+                for x in 0..dimensions.0 {
+                    let i = (y * dimensions.0 + x) as usize;
+                    let inside = x >= left && x < left + width && y >= top && y < top + height;
+                    let border = x < left + quiet_zone
+                        || x >= left + width - quiet_zone
+                        || y < top + quiet_zone
+                        || y >= top + height - quiet_zone;
+                    if !inside {
+                        assert_eq!(image.pixels[i], original.pixels[i], "{:?}", placement);
+                    } else if border {
+                        assert_eq!(image.pixels[i].r, u8::MAX, "{:?}", placement);
+                    }
+                }
+            }
+
+            let read = image
+                .read_payload()
+                .expect("Could not read payload")
+                .data()
+                .expect("Could not read data");
+            assert_eq!(data, read, "{:?}", placement);
+        }
+
+        let mut image = random_image(dimensions);
+        let outside = Baking {
+            placement: Placement::Pixels {
+                x: 200,
+                y: 0,
+                width: 121,
+                height: 100,
+            },
+            ..Baking::default()
+        };
+        assert_eq!(
+            image.bake_payload_with(&payload, outside),
+            Err(Error::PlacementOutsideImage)
+        );
+        let all_border = Baking {
+            quiet_zone: 120,
+            ..Baking::default()
+        };
+        assert_eq!(
+            image.bake_payload_with(&payload, all_border),
+            Err(Error::ImageTooSmall)
+        );
+    }
+
+    #[test]
+    fn read_several_payloads() {
+        let mut rng = rand::thread_rng();
+        let dimensions = (400, 300);
+        let mut image = random_image(dimensions);
+
+        // One panel per quadrant, baked in a jumbled order.
+        let panels = [(1, 0), (0, 1), (0, 0), (1, 1)];
+        let mut baked = Vec::new();
+        for &(column, row) in &panels {
+            let data: Vec<u8> = (0..rng.gen_range(20, 80)).map(|_| rng.gen()).collect();
+            let payload = Payload::new(&data).expect("Could not create payload");
+            let baking = Baking {
+                placement: Placement::Fraction {
+                    x: column as f32 * 0.5,
+                    y: row as f32 * 0.5,
+                    width: 0.5,
+                    height: 0.5,
+                },
+                quiet_zone: 3,
+                ..Baking::default()
+            };
+            image
+                .bake_payload_with(&payload, baking)
+                .expect("Could not bake payload");
+            baked.push(((row, column), data));
+        }
+        baked.sort();
+
+        let readings = image.read_payloads();
+        assert_eq!(readings.len(), baked.len());
+        for (reading, ((row, column), data)) in readings.iter().zip(&baked) {
+            assert_eq!(&reading.payload.data().expect("Could not read data"), data);
+            let (left, top) = (column * 200, row * 150);
+            for &(x, y) in &reading.corners() {
+                assert!(
+                    x >= left as f32 && x <= left as f32 + 200.0,
+                    "{:?}",
+                    reading.corners()
+                );
+                assert!(
+                    y >= top as f32 && y <= top as f32 + 150.0,
+                    "{:?}",
+                    reading.corners()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tiled_payload_merges_damaged_copies() {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..60).map(|_| rng.gen()).collect();
+        let payload = Payload::with_error_correction(&data, ErrorCorrection::Low)
+            .expect("Could not create payload");
+        let dimensions = (300, 300);
+        let mut image = random_image(dimensions);
+        let baking = Baking {
+            quiet_zone: 2,
+            tiles: (2, 2),
+            ..Baking::default()
+        };
+        image
+            .bake_payload_with(&payload, baking)
+            .expect("Could not bake payload");
+
+        // Scribble over a different band of each tile, past its header, so that no copy can be
+        // read alone.
+        for tile in 0..4u32 {
+            let (left, top) = (tile % 2 * 150, tile / 2 * 150);
+            let band = 90 + tile % 2 * 22..114 + tile % 2 * 22;
+            for y in 0..150 {
+                for x in 0..150 {
+                    let damaged = if tile < 2 { y } else { x };
+                    if band.contains(&damaged) {
+                        image.pixels[((top + y) * dimensions.0 + left + x) as usize] = Pixel {
+                            r: rng.gen(),
+                            g: rng.gen(),
+                            b: rng.gen(),
+                            a: u8::MAX,
+                        };
+                    }
+                }
+            }
+        }
+        let copies = image.read_payloads();
+        assert_eq!(copies.len(), 4);
+        for copy in &copies {
+            assert!(copy.payload.decode().is_err());
+        }
+
+        let read = image
+            .read_payload()
+            .expect("Could not read payload")
+            .data()
+            .expect("Could not read data");
+        assert_eq!(data, read);
+
+        // Copies cut short by cropping still vote for what they have.
+        let height = 245;
+        let cropped = Image::new(
+            (dimensions.0, height),
+            &image.pixels[..(dimensions.0 * height) as usize],
+        );
+        let copies = cropped.read_payloads();
+        assert_eq!(copies.len(), 4);
+        assert!(copies.iter().any(|copy| copy.payload.width < payload.width));
+        let read = cropped
+            .read_payload()
+            .expect("Could not read payload")
+            .data()
+            .expect("Could not read data");
+        assert_eq!(data, read);
+    }
+
+    #[test]
+    fn cropped_tile_merges_with_confidence() {
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..60).map(|_| rng.gen()).collect();
+        let payload = Payload::with_error_correction(&data, ErrorCorrection::Low)
+            .expect("Could not create payload");
+        let dimensions = (400, 200);
+        let mut image = random_image(dimensions);
+        let baking = Baking {
+            quiet_zone: 2,
+            tiles: (2, 1),
+            ..Baking::default()
+        };
+        image
+            .bake_payload_with(&payload, baking)
+            .expect("Could not bake payload");
+
+        // Scribble over a band of the first tile, past its header, then crop the end of the second
+        // tile away, so that neither copy can be read alone.
+        for y in 0..dimensions.1 {
+            for x in 125..160 {
+                image.pixels[(y * dimensions.0 + x) as usize] = Pixel {
+                    r: rng.gen(),
+                    g: rng.gen(),
+                    b: rng.gen(),
+                    a: u8::MAX,
+                };
+            }
+        }
+        let width = 370;
+        let pixels: Vec<Pixel> = image
+            .pixels
+            .chunks(dimensions.0 as usize)
+            .flat_map(|row| row[..width as usize].iter().copied())
+            .collect();
+        let cropped = Image::new((width, dimensions.1), pixels);
+        let copies = cropped.read_payloads();
+        assert_eq!(copies.len(), 2);
+        for copy in &copies {
+            assert!(copy.payload.decode().is_err());
+        }
+
+        // Reading with confidence, as the reader binary does, still merges the copies.
+        let reading = cropped
+            .read_payload_with_confidence()
+            .expect("Could not read payload");
+        assert_eq!(reading.payload.data().expect("Could not read data"), data);
+    }
+
+    #[test]
+    fn payload_repair() {
+        let mut rng = rand::thread_rng();
+
+        let data: Vec<u8> = (0..200).map(|_| rng.gen()).collect();
+        let payload = Payload::with_error_correction(&data, ErrorCorrection::Quartile)
+            .expect("Could not create payload");
+        let width = payload.width as usize;
+
+        // Scribble over a handful of superpixels past the target and preamble.
+        let mut damaged: Vec<Superpixel> = payload.data.clone();
+        for index in &[width * 4 + 5, width * 6 + 2, width * 7 + 7, width * 9 + 1] {
+            damaged[*index] = match damaged[*index] {
+                Superpixel::Value(value) => Superpixel::Value(63 - value),
+                _ => Superpixel::Value(21),
+            };
+        }
+
+        let decoded = Payload::from_raw(payload.width, damaged)
+            .expect("Could not load payload")
+            .decode()
+            .expect("Could not repair data");
+        assert_eq!(data, decoded.data);
+        assert_eq!(decoded.corrected, 4);
+    }
+
+    #[test]
+    fn confidence_grid() {
+        let mut rng = rand::thread_rng();
+
+        let mut image = random_image((120, 120));
+        let data: Vec<u8> = (0..200).map(|_| rng.gen()).collect();
+        image
+            .bake_payload(&Payload::new(&data).expect("Could not create payload"))
+            .expect("Could not bake payload");
+        let grid = image.locate().expect("Could not locate payload");
+
+        // Whiten a few columns of pixels in one superpixel, short of a majority.
+        let (cell_x, cell_y) = (grid.columns() - 2, grid.rows() - 2);
+        let (left, top) = grid.to_image(cell_x as f32, cell_y as f32);
+        let (right, bottom) = grid.to_image(cell_x as f32 + 1.0, cell_y as f32 + 1.0);
+        for y in top as u32..bottom as u32 {
+            for x in (left as u32..right as u32).step_by(3) {
+                image.pixels[(y * image.dimensions.0 + x) as usize] = Pixel {
+                    r: u8::MAX,
+                    g: u8::MAX,
+                    b: u8::MAX,
+                    a: u8::MAX,
+                };
+            }
+        }
+
+        let reading = image
+            .read_payload_with_confidence()
+            .expect("Could not read payload");
+        assert_eq!(reading.payload.data().expect("Could not read data"), data);
+
+        let damaged = reading
+            .confidence
+            .get(cell_x, cell_y)
+            .expect("Damaged superpixel is outside the grid");
+        assert!(damaged.share > 0.5 && damaged.share < 0.9, "{:?}", damaged);
+        assert_eq!(damaged.runner_up, Some(Superpixel::White));
+        let clean = reading.confidence.get(4, 4).expect("Missing superpixel");
+        assert_eq!(clean.share, 1.0);
+        assert_eq!(clean.runner_up, None);
+        assert!(clean.pixels > 0);
+
+        let heatmap = image.confidence_heatmap(&reading);
+        assert_eq!(heatmap.dimensions(), image.dimensions());
+        let heat_at = |x: f32, y: f32| {
+            let (x, y) = grid.to_image(x + 0.5, y + 0.5);
+            heatmap.pixels()[(y as u32 * heatmap.dimensions().0 + x as u32) as usize]
+        };
+        assert!(heat_at(cell_x as f32, cell_y as f32).r > 0);
+        assert_eq!(heat_at(4.0, 4.0).r, 0);
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        let header = Header {
+            compression: Compression::Gzip,
+            codec: Codec::Bincode,
+            error_correction: ErrorCorrection::High,
+            ..Header::default()
+        };
+        let decoded = Payload::with_header(b"song", header)
+            .expect("Could not create payload")
+            .decode()
+            .expect("Could not decode payload");
+        assert_eq!(decoded.header, header);
+        assert_eq!(decoded.data, b"song");
+        assert_eq!(decoded.signature, None);
+
+        let signature = Signature {
+            public_key: [3; PUBLIC_KEY_LEN],
+            signature: [5; SIGNATURE_LEN],
+        };
+        let decoded = Payload::with_encoding(
+            b"song",
+            Encoding {
+                header,
+                signature: Some(signature),
+                ..Encoding::default()
+            },
+        )
+        .expect("Could not create payload")
+        .decode()
+        .expect("Could not decode payload");
+        assert!(decoded.header.flags.contains(Flags::SIGNED));
+        assert_eq!(decoded.data, b"song");
+        assert_eq!(decoded.signature, Some(signature));
+    }
+
+    #[test]
+    fn framed_payload_roundtrip() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let header = Header {
+            compression: Compression::Gzip,
+            ..Header::default()
+        };
+        let signature = Signature {
+            public_key: [3; PUBLIC_KEY_LEN],
+            signature: [5; SIGNATURE_LEN],
+        };
+        let frames = Payload::with_frames(
+            &data,
+            Encoding {
+                header,
+                signature: Some(signature),
+                ..Encoding::default()
+            },
+            3,
+        )
+        .expect("Could not create frames");
+        assert_eq!(frames.len(), 3);
+        let single = Payload::new(&data).expect("Could not create payload");
+        assert!(frames.iter().all(|frame| frame.width < single.width));
+
+        let mut decoded: Vec<Decoded> = frames
+            .iter()
+            .map(|frame| frame.decode().expect("Could not decode frame"))
+            .collect();
+        for (index, frame) in decoded.iter().enumerate() {
+            assert_eq!(
+                frame.header.frame,
+                Some(Frame {
+                    index: index as u16,
+                    count: 3
+                })
+            );
+            assert_eq!(frame.signature.is_some(), index == 0);
+        }
+
+        // Frames can come in any order, and repeats are harmless.
+        decoded.swap(0, 2);
+        decoded.push(decoded[1].clone());
+        let joined = Decoded::join(decoded.clone()).expect("Could not join frames");
+        assert_eq!(joined.data, data);
+        assert_eq!(
+            joined.header,
+            Header {
+                flags: Flags::SIGNED,
+                ..header
+            }
+        );
+        assert_eq!(joined.signature, Some(signature));
+
+        decoded.retain(|frame| frame.header.frame.map(|frame| frame.index) != Some(1));
+        assert_eq!(Decoded::join(decoded), Err(Error::MissingFrame(1)));
+
+        // Unframed payloads pass through on their own, but can't be joined with frames.
+        let unframed = single.decode().expect("Could not decode payload");
+        assert_eq!(Decoded::join(vec![unframed.clone()]), Ok(unframed.clone()));
+        let first = frames[0].decode().expect("Could not decode frame");
+        assert_eq!(
+            Decoded::join(vec![first, unframed]),
+            Err(Error::MismatchedFrames)
+        );
+    }
+
+    #[test]
+    fn capacity_fits_superpixel_minimum() {
+        let dimensions = (400, 300);
+        let encodings = [
+            Encoding::default(),
+            Encoding {
+                layout: Layout::Finders,
+                density: Density::Robust,
+                ..Encoding::default()
+            },
+            Encoding {
+                header: Header {
+                    error_correction: ErrorCorrection::High,
+                    ..Header::default()
+                },
+                density: Density::Densest,
+                signature: Some(Signature {
+                    public_key: [0; PUBLIC_KEY_LEN],
+                    signature: [0; SIGNATURE_LEN],
+                }),
+                ..Encoding::default()
+            },
+            Encoding {
+                aspect: Some(dimensions),
+                ..Encoding::default()
+            },
+        ];
+        let bakings = [
+            Baking::default(),
+            Baking::jpeg(),
+            Baking {
+                quiet_zone: 10,
+                tiles: (2, 1),
+                ..Baking::default()
+            },
+        ];
+        for &encoding in &encodings {
+            for &baking in &bakings {
+                for &minimum in &[3, 8, 20] {
+                    let capacity = match Payload::capacity(dimensions, minimum, encoding, baking) {
+                        Ok(capacity) => capacity,
+                        Err(error) => {
+                            assert_eq!(error, Error::ImageTooSmall);
+                            continue;
+                        }
+                    };
+                    let plan = Payload::plan(dimensions, capacity, encoding, baking)
+                        .expect("Could not plan payload");
+                    assert!(plan.superpixel_size.0.min(plan.superpixel_size.1) >= minimum);
+                    let payload = Payload::with_encoding(vec![7; capacity], encoding)
+                        .expect("Could not create payload");
+                    assert_eq!((payload.width, payload.height), (plan.width, plan.height));
+
+                    // One more byte needs a wider grid of smaller superpixels, if any fit.
+                    if let Ok(plan) = Payload::plan(dimensions, capacity + 1, encoding, baking) {
+                        assert!(plan.superpixel_size.0.min(plan.superpixel_size.1) < minimum);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rectangular_payload_roundtrip() {
+        let mut rng = rand::thread_rng();
+        let dimensions = (1600, 400);
+        let encoding = Encoding {
+            aspect: Some(dimensions),
+            ..Encoding::default()
+        };
+        let minimum = 8;
+        let baking = Baking::default();
+        let square = Payload::capacity(dimensions, minimum, Encoding::default(), baking)
+            .expect("Could not find capacity");
+        let capacity = Payload::capacity(dimensions, minimum, encoding, baking)
+            .expect("Could not find capacity");
+        assert!(capacity > square * 2);
+
+        let data: Vec<u8> = (0..capacity).map(|_| rng.gen()).collect();
+        let payload = Payload::with_encoding(&data, encoding).expect("Could not create payload");
+        assert!(payload.width > payload.height * 3);
+        let decoded = payload.decode().expect("Could not decode payload");
+        assert_eq!(
+            decoded.header.grid,
+            Some((payload.width as u16, payload.height as u16))
+        );
+        assert_eq!(decoded.data, data);
+
+        // Tall images get tall grids, and finder patterns stay square.
+        let tall = Payload::with_encoding(
+            &data[..square],
+            Encoding {
+                aspect: Some((400, 1600)),
+                ..Encoding::default()
+            },
+        )
+        .expect("Could not create payload");
+        assert!(tall.height > tall.width);
+        let finders = Payload::with_encoding(
+            &data[..square],
+            Encoding {
+                layout: Layout::Finders,
+                ..encoding
+            },
+        )
+        .expect("Could not create payload");
+        assert_eq!(finders.width, finders.height);
+
+        for payload in &[payload, tall] {
+            let dimensions = if payload.width > payload.height {
+                dimensions
+            } else {
+                (dimensions.1, dimensions.0)
+            };
+            let mut image = Image::new(
+                dimensions,
+                vec![
+                    Pixel {
+                        r: 100,
+                        g: 150,
+                        b: 200,
+                        a: 255,
+                    };
+                    dimensions.0 as usize * dimensions.1 as usize
+                ],
+            );
+            image.bake_payload(payload).expect("Could not bake payload");
+            let read = image.read_payload().expect("Could not read payload");
+            assert_eq!((read.width, read.height), (payload.width, payload.height));
+            assert_eq!(
+                read.data().expect("Could not read data"),
+                payload.decode().expect("Could not decode payload").data
+            );
+        }
+    }
+
+    #[test]
+    fn interleaving_spreads_burst_damage() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 256) as u8).collect();
+        for &interleaved in &[false, true] {
+            let mut header = Header::default();
+            if interleaved {
+                header.flags.insert(Flags::INTERLEAVED);
+            }
+            let mut payload =
+                Payload::with_header(&data, header).expect("Could not create payload");
+
+            // Wipe out a run of superpixels in reading order, well past the header, as a sticker
+            // over one patch of the image would.
+            let cells: Vec<usize> = (0..payload.data.len())
+                .map(spiral_position)
+                .filter(|&(x, y)| payload.layout.pattern(payload.width, x, y).is_none())
+                .map(|(x, y)| (y * payload.width + x) as usize)
+                .collect();
+            for &cell in &cells[300..450] {
+                payload.data[cell] = Superpixel::Value(0);
+            }
+
+            let decoded = payload.decode();
+            if interleaved {
+                assert_eq!(decoded.expect("Could not decode payload").data, data);
+            } else {
+                assert!(decoded.is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn baking_in_place() {
+        let owned = random_image((300, 300));
+        let mut rgba: Vec<u8> = Pixel::as_rgba(owned.pixels()).to_vec();
+        assert_eq!(Pixel::from_rgba(&rgba).unwrap(), owned.pixels());
+        assert!(Pixel::from_rgba(&rgba[1..]).is_none());
+
+        let payload = Payload::new(b"Baked without a copy").expect("Could not create payload");
+        let mut baked = owned.clone();
+        baked
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
+        let mut view = Image::from_buffer((300, 300), Pixel::from_rgba_mut(&mut rgba).unwrap())
+            .expect("Could not view buffer");
+        view.bake_payload(&payload).expect("Could not bake payload");
+        assert_eq!(
+            view.read_payload().expect("Could not read payload"),
+            payload
+        );
+        assert_eq!(rgba, Pixel::as_rgba(baked.pixels()));
+        assert_eq!(
+            Image::from_buffer((300, 299), Pixel::from_rgba(&rgba).unwrap()),
+            Err(Error::InvalidDimensions)
+        );
+    }
+
+    #[test]
+    fn malformed_images_are_errors() {
+        let gray = Pixel {
+            r: 128,
+            g: 128,
+            b: 128,
+            a: 255,
+        };
+        assert_eq!(
+            Image::try_new((4, 4), vec![gray; 15]),
+            Err(Error::InvalidDimensions)
+        );
+        for &dimensions in &[(0, 0), (1, 1), (0, 7), (7, 0), (1, 500), (500, 1), (3, 3)] {
+            let image = Image::new(
+                dimensions,
+                vec![gray; (dimensions.0 * dimensions.1) as usize],
+            );
+            assert!(image.read_payload().is_err());
+            assert!(image.superpixel_width().is_err());
+            assert!(image.read_payloads().is_empty());
+        }
+        for width in 0..4 {
+            let black = vec![Superpixel::Black; width * width];
+            assert!(Payload::from_raw(width as u32, black).is_err());
+        }
+
+        // A payload cut down to its first rows of superpixels, one pixel each.
+        let payload = Payload::new(b"A song cut short").expect("Could not create payload");
+        let mut image = Image::new(
+            (payload.width, payload.width),
+            vec![gray; (payload.width * payload.width) as usize],
+        );
+        image
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
+        for rows in 0..payload.width {
+            let cropped = Image::new(
+                (payload.width, rows),
+                &image.pixels[..(payload.width * rows) as usize],
+            );
+            // The last rows may only hold padding, but anything read has to be the song.
+            if let Ok(data) = cropped.read_payload().and_then(|payload| payload.data()) {
+                assert_eq!(data, b"A song cut short");
+            }
+        }
+    }
+
+    /// Already-encoded bytes laid out in the smallest square grid that holds them.
+    fn square_payload(bytes: &[u8]) -> Payload {
+        let density = Density::Standard;
+        let width = Layout::Target.width_for(MARKER_LEN + density.bytes_to_values(bytes).len());
+        Payload::from_bytes(bytes, Layout::Target, density, (width, width))
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        // A well-formed lead from some future version
+        let mut lead = b"IM\x02\x06".to_vec();
+        lead.extend(reed_solomon::encode(&lead, 4));
+        lead.resize(64, 0);
+        assert_eq!(
+            square_payload(&lead).decode(),
+            Err(Error::UnsupportedVersion(2))
+        );
+
+        // Images from before the header existed were just a length prefix and gzip data
+        let mut legacy = vec![0, 20, 0x1f, 0x8b];
+        legacy.resize(22, 0);
+        assert_eq!(square_payload(&legacy).decode(), Err(Error::NoHeader));
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let data = b"Some data that is not what the checksum says it is";
+        let header = Header::default();
+        let mut bytes = header.encode(data.len() as u32, checksum(b"Something else"));
+        bytes.extend(header.error_correction.protect(data));
+        assert_eq!(
+            square_payload(&bytes).decode(),
+            Err(Error::ChecksumMismatch)
+        );
+    }
+
     #[test]
     fn rows_and_columns() {
         use Superpixel::*;
         let payload = Payload {
+            layout: Layout::Target,
+            density: Density::Standard,
             width: 4,
+            height: 4,
             data: vec![
                 Black,
                 White,
@@ -691,7 +3144,10 @@ mod test {
     fn unwrapped_payload() {
         use Superpixel::*;
         let mut payload = Payload {
+            layout: Layout::Target,
+            density: Density::Standard,
             width: 4,
+            height: 4,
             data: vec![
                 Value(0),
                 Value(1),
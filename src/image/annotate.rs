@@ -0,0 +1,238 @@
+use super::confidence::grayed_out;
+use super::{grid_position, Density, Image, Payload, Pixel, Reading, Superpixel, MARKER_LEN};
+
+/// What a superpixel of a payload is there for.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum Role {
+    /// Part of the layout's patterns, such as the target, which let a reader find the payload.
+    Pattern,
+    /// Part of the density marker.
+    Marker,
+    /// Part of the header.
+    Header,
+    /// Part of the data, error correction included.
+    Data,
+    /// Filler after the data, or the art around the payload when the grid was read past it.
+    Padding,
+}
+
+/// Drawn along the edges of superpixels.
+const BOUNDARY: Pixel = Pixel {
+    r: u8::MAX,
+    g: 0,
+    b: u8::MAX,
+    a: u8::MAX,
+};
+
+/// Drawn for the black and white superpixels of the layout's patterns.
+const PATTERN_BLACK: Pixel = Pixel {
+    r: 0,
+    g: 64,
+    b: 96,
+    a: u8::MAX,
+};
+const PATTERN_WHITE: Pixel = Pixel {
+    r: 160,
+    g: u8::MAX,
+    b: u8::MAX,
+    a: u8::MAX,
+};
+
+/// Drawn in stripes over padding.
+const HATCHING: Pixel = Pixel {
+    r: 64,
+    g: 64,
+    b: 64,
+    a: u8::MAX,
+};
+
+impl Payload {
+    /// What each superpixel is there for, in row-major order.  If the header can't be read,
+    /// everything after the density marker is taken to be data.
+    pub fn roles(&self) -> Vec<Role> {
+        let bits = self.density.bits() as usize;
+        let (header_values, values) = match self.parse_header() {
+            Ok(parsed) => {
+                let protected = parsed
+                    .header
+                    .error_correction
+                    .protected_len(parsed.length as usize);
+                (
+                    (parsed.header_len * 8).div_ceil(bits),
+                    ((parsed.header_len + protected) * 8).div_ceil(bits),
+                )
+            }
+            Err(_) => (0, usize::MAX),
+        };
+
+        let width = self.width as usize;
+        let mut roles = vec![Role::Padding; self.data.len()];
+        let mut cells = 0;
+        for i in 0..roles.len() {
+            let (x, y) = grid_position(i, self.dimensions());
+            let role = if self.layout.pattern(self.width, x, y).is_some() {
+                Role::Pattern
+            } else {
+                cells += 1;
+                match cells - 1 {
+                    cell if cell < MARKER_LEN => Role::Marker,
+                    cell if cell - MARKER_LEN < header_values => Role::Header,
+                    cell if cell - MARKER_LEN < values => Role::Data,
+                    _ => Role::Padding,
+                }
+            };
+            roles[y as usize * width + x as usize] = role;
+        }
+        roles
+    }
+}
+
+/// The color of a value at a density, with each channel's bits stretched over the whole channel
+/// so that neighbouring values stand apart.
+fn value_color(density: Density, value: u16) -> Pixel {
+    let bits = density.bits_per_channel();
+    let max = (1 << bits) - 1;
+    let channel = |shift: u32| ((value >> shift & max) * u8::MAX as u16 / max) as u8;
+    if density.is_grayscale() {
+        let luma = channel(0);
+        Pixel {
+            r: luma,
+            g: luma,
+            b: luma,
+            a: u8::MAX,
+        }
+    } else {
+        Pixel {
+            r: channel(bits * 2),
+            g: channel(bits),
+            b: channel(0),
+            a: u8::MAX,
+        }
+    }
+}
+
+impl<P: AsRef<[Pixel]>> Image<P> {
+    /// Draw what a reading made of this image, which it must have been read from.  Every
+    /// superpixel is filled with the value read from it, with each channel's bits stretched to
+    /// the whole channel, and outlined where the grid put its edges.  The layout's patterns, such
+    /// as the target, are drawn in cyan, padding is faded and striped, and everything outside the
+    /// grid is grayed out.
+    pub fn annotated(&self, reading: &Reading) -> Image {
+        let payload = &reading.payload;
+        let roles = payload.roles();
+        let inverse = reading.grid.transform().inverse();
+        let cell = |x: u32, y: u32| {
+            inverse
+                .map(|inverse| inverse.apply(x as f64 + 0.5, y as f64 + 0.5))
+                .filter(|&(x, y)| x >= 0.0 && y >= 0.0)
+                .map(|(x, y)| (x as u32, y as u32))
+                .filter(|&(x, y)| x < payload.width && y < payload.height)
+        };
+
+        let (width, height) = self.dimensions;
+        let mut pixels = Vec::with_capacity(self.pixels().len());
+        for y in 0..height {
+            for x in 0..width {
+                let here = cell(x, y);
+                let boundary = (x + 1 < width && cell(x + 1, y) != here)
+                    || (y + 1 < height && cell(x, y + 1) != here);
+                if boundary {
+                    pixels.push(BOUNDARY);
+                    continue;
+                }
+                let (cell_x, cell_y) = match here {
+                    Some(here) => here,
+                    None => {
+                        pixels.push(grayed_out(self.pixels()[(y * width + x) as usize]));
+                        continue;
+                    }
+                };
+
+                let index = (cell_y * payload.width + cell_x) as usize;
+                let superpixel = payload.data[index];
+                pixels.push(match roles[index] {
+                    Role::Pattern => match superpixel {
+                        Superpixel::Black => PATTERN_BLACK,
+                        _ => PATTERN_WHITE,
+                    },
+                    Role::Padding if (x + y) % 8 < 2 => HATCHING,
+                    Role::Padding => {
+                        let color = value_color(payload.density, payload.density.value(superpixel));
+                        Pixel {
+                            r: color.r / 2 + 64,
+                            g: color.g / 2 + 64,
+                            b: color.b / 2 + 64,
+                            a: u8::MAX,
+                        }
+                    }
+                    _ => value_color(payload.density, payload.density.value(superpixel)),
+                });
+            }
+        }
+        Image::new(self.dimensions, pixels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::{ErrorCorrection, Header};
+
+    #[test]
+    fn annotated_roles() {
+        let data = b"Annotated to see what went wrong";
+        let payload = Payload::new(data).expect("Could not create payload");
+        let roles = payload.roles();
+        let count = |role| roles.iter().filter(|&&r| r == role).count();
+        let encoded =
+            Header::default().encoded_len() + ErrorCorrection::default().protected_len(data.len());
+        let values = (encoded * 8).div_ceil(payload.density.bits() as usize);
+        assert_eq!(count(Role::Marker), MARKER_LEN);
+        assert_eq!(count(Role::Header) + count(Role::Data), values);
+        assert_eq!(
+            count(Role::Pattern) + MARKER_LEN + values + count(Role::Padding),
+            roles.len()
+        );
+        // The filler comes last around the spiral.
+        let last = (0..roles.len())
+            .map(|i| grid_position(i, payload.dimensions()))
+            .map(|(x, y)| roles[(y * payload.width + x) as usize])
+            .filter(|&role| role != Role::Pattern)
+            .last();
+        assert_eq!(last, Some(Role::Padding));
+
+        let gray = Pixel {
+            r: 128,
+            g: 128,
+            b: 128,
+            a: u8::MAX,
+        };
+        let mut image = Image::new((160, 160), vec![gray; 160 * 160]);
+        image
+            .bake_payload(&payload)
+            .expect("Could not bake payload");
+        let reading = image
+            .read_payload_with_confidence()
+            .expect("Could not read payload");
+        let annotated = image.annotated(&reading);
+        assert_eq!(annotated.dimensions(), image.dimensions());
+        let at = |(x, y): (u32, u32)| {
+            let (x, y) = reading.grid.to_image(x as f32 + 0.5, y as f32 + 0.5);
+            annotated.pixels()[(y as u32 * annotated.dimensions().0 + x as u32) as usize]
+        };
+        let roles = reading.payload.roles();
+        for y in 0..reading.payload.height {
+            for x in 0..reading.payload.width {
+                let index = (y * reading.payload.width + x) as usize;
+                let superpixel = reading.payload.data[index];
+                let expected = match roles[index] {
+                    Role::Pattern if superpixel == Superpixel::Black => PATTERN_BLACK,
+                    Role::Pattern => PATTERN_WHITE,
+                    Role::Padding => continue,
+                    _ => value_color(payload.density, payload.density.value(superpixel)),
+                };
+                assert_eq!(at((x, y)), expected, "at {:?}", (x, y));
+            }
+        }
+    }
+}
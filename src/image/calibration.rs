@@ -0,0 +1,151 @@
+use super::{Grid, Image, Layout, Pixel, Superpixel, BLACK_BELOW, WHITE_ABOVE};
+
+/// The least difference between the black and white levels of every channel for a measurement to
+/// be trusted.
+const MIN_CONTRAST: f32 = 64.0;
+
+/// Share of pixels at each end of every channel that stretching ignores, so that a few stray
+/// pixels don't set the levels for the whole image.
+const STRETCH_CLIP: f32 = 0.005;
+
+/// The levels each channel of an image shows black and white at, as measured from the black and
+/// white superpixels of a layout's patterns.  Every channel is stretched from these levels back
+/// out to the full range before it is read, which undoes brightness, contrast, and white balance
+/// shifts from screenshots and photos.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Calibration {
+    pub black: [f32; 3],
+    pub white: [f32; 3],
+}
+
+impl Default for Calibration {
+    /// Black and white at the ends of the range, leaving every pixel as it is.
+    fn default() -> Self {
+        Calibration {
+            black: [0.0; 3],
+            white: [255.0; 3],
+        }
+    }
+}
+
+impl Calibration {
+    /// Stretch a pixel's channels from these levels out to the full range.
+    pub fn apply(&self, pixel: Pixel) -> Pixel {
+        let channel = |value: u8, i: usize| {
+            let (black, white) = (self.black[i], self.white[i]);
+            ((value as f32 - black) * 255.0 / (white - black))
+                .round()
+                .clamp(0.0, 255.0) as u8
+        };
+        Pixel {
+            r: channel(pixel.r, 0),
+            g: channel(pixel.g, 1),
+            b: channel(pixel.b, 2),
+            a: pixel.a,
+        }
+    }
+
+    /// The smallest difference between the black and white levels of any channel.
+    fn contrast(&self) -> f32 {
+        (0..3)
+            .map(|i| self.white[i] - self.black[i])
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// Levels from the median of each channel of pixels that should be black and pixels that
+    /// should be white, if they are far enough apart to be trusted.
+    fn from_samples(blacks: &[Pixel], whites: &[Pixel]) -> Option<Calibration> {
+        if blacks.is_empty() || whites.is_empty() {
+            return None;
+        }
+        let levels = |pixels: &[Pixel]| {
+            let median = |channel: fn(&Pixel) -> u8| {
+                let mut values: Vec<u8> = pixels.iter().map(channel).collect();
+                values.sort_unstable();
+                values[values.len() / 2] as f32
+            };
+            [median(|p| p.r), median(|p| p.g), median(|p| p.b)]
+        };
+        let calibration = Calibration {
+            black: levels(blacks),
+            white: levels(whites),
+        };
+        if calibration.contrast() >= MIN_CONTRAST {
+            Some(calibration)
+        } else {
+            None
+        }
+    }
+}
+
+impl<P: AsRef<[Pixel]>> Image<P> {
+    /// Measure the black and white levels of a grid from the patterns of whichever layout shows
+    /// them most clearly, falling back to leaving every pixel as it is.
+    pub(crate) fn calibrate(&self, grid: &Grid) -> Calibration {
+        let grid_size = grid.columns().min(grid.rows());
+        Layout::ALL
+            .iter()
+            .filter(|layout| grid_size >= layout.min_width())
+            .filter_map(|&layout| {
+                let (mut blacks, mut whites) = (Vec::new(), Vec::new());
+                for y in 0..grid_size {
+                    for x in 0..grid_size {
+                        let samples = match layout.pattern(grid_size, x, y) {
+                            Some(Superpixel::Black) => &mut blacks,
+                            Some(Superpixel::White) => &mut whites,
+                            _ => continue,
+                        };
+                        samples.extend(self.sample_pixels(grid, x, y));
+                    }
+                }
+                Calibration::from_samples(&blacks, &whites)
+            })
+            .max_by(|a, b| {
+                a.contrast()
+                    .partial_cmp(&b.contrast())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or_default()
+    }
+
+    /// A copy of this image with every channel stretched between its own darkest and lightest
+    /// values, so that a target too dim or washed out to be found as it is can be found in it.
+    /// None if the image already reaches black and white.
+    pub(crate) fn stretched(&self) -> Option<Image> {
+        let mut histograms = [[0u32; 256]; 3];
+        for pixel in self.pixels() {
+            histograms[0][pixel.r as usize] += 1;
+            histograms[1][pixel.g as usize] += 1;
+            histograms[2][pixel.b as usize] += 1;
+        }
+        let clip = (self.pixels().len() as f32 * STRETCH_CLIP) as u32;
+        let level = |histogram: &[u32; 256], values: Vec<usize>| {
+            let mut seen = 0;
+            values
+                .into_iter()
+                .find(|&value| {
+                    seen += histogram[value];
+                    seen > clip
+                })
+                .unwrap_or(0) as f32
+        };
+
+        let mut calibration = Calibration::default();
+        for (i, histogram) in histograms.iter().enumerate() {
+            calibration.black[i] = level(histogram, (0..256).collect());
+            calibration.white[i] = level(histogram, (0..256).rev().collect());
+        }
+        let untouched = (0..3).all(|i| {
+            calibration.black[i] < BLACK_BELOW as f32 && calibration.white[i] > WHITE_ABOVE as f32
+        });
+        if untouched || calibration.contrast() < MIN_CONTRAST {
+            return None;
+        }
+        let pixels: Vec<Pixel> = self
+            .pixels()
+            .iter()
+            .map(|&p| calibration.apply(p))
+            .collect();
+        Some(Image::new(self.dimensions, pixels))
+    }
+}
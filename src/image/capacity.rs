@@ -0,0 +1,112 @@
+use super::{Baking, Encoding, Error, Header, Payload, Signature, MARKER_LEN};
+
+/// How a payload comes out when baked into an image.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct Plan {
+    /// Width of the payload's grid, in superpixels.
+    pub width: u32,
+    /// Height of the payload's grid, in superpixels.
+    pub height: u32,
+    /// Width and height of each superpixel, in pixels.
+    pub superpixel_size: (u32, u32),
+}
+
+impl Encoding {
+    /// The columns and rows of the grid holding this many bytes of data once it is protected,
+    /// following the aspect ratio if there is one.
+    pub(crate) fn grid_for(&self, protected_len: usize) -> (u32, u32) {
+        let bits = self.density.bits() as usize;
+        // Values in the header, and in all, after the density marker.
+        let values = |header: Header| {
+            let header_len = header.encoded_len();
+            (
+                MARKER_LEN + (header_len * 8).div_ceil(bits),
+                MARKER_LEN + ((header_len + protected_len) * 8).div_ceil(bits),
+            )
+        };
+        let square = self.layout.width_for(
+            values(Header {
+                grid: None,
+                ..self.header
+            })
+            .1,
+        );
+        let aspect = match self.aspect {
+            Some(aspect) => aspect,
+            None => return (square, square),
+        };
+        // A grid that isn't square gives its columns and rows in the header, which has to fit in
+        // the square at its corner for a reader to find them.
+        let (corner, values) = values(Header {
+            grid: Some((0, 0)),
+            ..self.header
+        });
+        match self.layout.dimensions_for(values, corner, aspect) {
+            (columns, rows) if columns == rows => (square, square),
+            dimensions => dimensions,
+        }
+    }
+
+    /// The columns and rows of the grid a payload of this many bytes is laid out in.
+    pub fn dimensions_for(&self, len: usize) -> (u32, u32) {
+        let len = len + self.signature.map_or(0, |_| Signature::LEN);
+        self.grid_for(self.header.error_correction.protected_len(len))
+    }
+
+    /// The most bytes a payload can hold in a grid of at most these columns and rows, or None
+    /// if not even an empty payload fits.
+    pub fn capacity(&self, (columns, rows): (u32, u32)) -> Option<usize> {
+        let fits = |len| {
+            let dimensions = self.dimensions_for(len);
+            dimensions.0 <= columns && dimensions.1 <= rows
+        };
+        if !fits(0) {
+            return None;
+        }
+        // Every superpixel holds less than two bytes, so this is always too many.
+        let (mut low, mut high) = (0, columns as usize * rows as usize * 2);
+        while low < high {
+            let middle = (low + high).div_ceil(2);
+            if fits(middle) {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        Some(low)
+    }
+}
+
+impl Payload {
+    /// The most bytes that can be baked into an image of these dimensions, keeping superpixels
+    /// at least this many pixels on each side.
+    pub fn capacity(
+        dimensions: (u32, u32),
+        min_superpixel_size: u32,
+        encoding: Encoding,
+        baking: Baking,
+    ) -> Result<usize, Error> {
+        let (width, height) = baking.grid_size(dimensions)?;
+        // Superpixels only come in whole blocks.
+        let block = baking.block.max(1);
+        let step = min_superpixel_size.max(1).div_ceil(block) * block;
+        encoding
+            .capacity((width / step, height / step))
+            .ok_or(Error::ImageTooSmall)
+    }
+
+    /// How this many bytes would come out when baked into an image of these dimensions.
+    pub fn plan(
+        dimensions: (u32, u32),
+        len: usize,
+        encoding: Encoding,
+        baking: Baking,
+    ) -> Result<Plan, Error> {
+        let (width, height) = encoding.dimensions_for(len);
+        Ok(Plan {
+            width,
+            height,
+            superpixel_size: baking.superpixel_size(dimensions, (width, height))?,
+        })
+    }
+}
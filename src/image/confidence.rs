@@ -0,0 +1,177 @@
+use super::{Calibration, Grid, Image, Payload, Pixel, Superpixel};
+
+/// How sure a read was of a single superpixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Confidence {
+    /// The share of sampled pixels that voted for the value that was read, from 0 to 1.
+    pub share: f32,
+
+    /// The value with the second most votes, if any pixel disagreed.
+    pub runner_up: Option<Superpixel>,
+
+    /// The number of pixels that were sampled.
+    pub pixels: u32,
+}
+
+impl Confidence {
+    /// Tally the votes for a superpixel, returning the winner along with how sure it is.  Ties go
+    /// to whichever value was voted for first.
+    pub(crate) fn tally(counted: &mut [(Superpixel, u32)]) -> (Superpixel, Confidence) {
+        counted.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        let pixels = counted.iter().map(|&(_, count)| count).sum();
+
+        match counted.first() {
+            Some(&(winner, count)) => (
+                winner,
+                Confidence {
+                    share: count as f32 / pixels as f32,
+                    runner_up: counted.get(1).map(|&(superpixel, _)| superpixel),
+                    pixels,
+                },
+            ),
+            None => (
+                Superpixel::Black,
+                Confidence {
+                    share: 0.0,
+                    runner_up: None,
+                    pixels: 0,
+                },
+            ),
+        }
+    }
+}
+
+/// Votes for the value of a superpixel, in a buffer that is reused from one superpixel to the
+/// next, so that reading a grid doesn't allocate for every superpixel.  Only a handful of values
+/// get any votes, so they are looked up by a plain search.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Votes(Vec<(Superpixel, u32)>);
+
+impl Votes {
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub(crate) fn add(&mut self, superpixel: Superpixel) {
+        match self.0.iter_mut().find(|(value, _)| *value == superpixel) {
+            Some((_, count)) => *count += 1,
+            None => self.0.push((superpixel, 1)),
+        }
+    }
+
+    /// The winning value, along with how sure it is.
+    pub(crate) fn tally(&mut self) -> (Superpixel, Confidence) {
+        Confidence::tally(&mut self.0)
+    }
+}
+
+/// Confidence for every superpixel of a read grid, in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfidenceGrid {
+    width: u32,
+    height: u32,
+    cells: Vec<Confidence>,
+}
+
+impl ConfidenceGrid {
+    pub(crate) fn new((width, height): (u32, u32), cells: Vec<Confidence>) -> Self {
+        ConfidenceGrid {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Get the confidence of the indexed superpixel.
+    pub fn get(&self, x: u32, y: u32) -> Option<&Confidence> {
+        if x >= self.width || y >= self.height {
+            None
+        } else {
+            self.cells.get((y * self.width + x) as usize)
+        }
+    }
+
+    pub fn cells(&self) -> &[Confidence] {
+        &self.cells
+    }
+}
+
+/// A payload read from an image, along with where it was found and how sure the read was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub grid: Grid,
+    pub payload: Payload,
+    pub confidence: ConfidenceGrid,
+
+    /// The black and white levels the payload was read at.
+    pub calibration: Calibration,
+}
+
+impl Reading {
+    /// The corners of the payload in image pixel coordinates, starting from the target's corner
+    /// and going along the grid's first axis.  Only the superpixels the header says were encoded
+    /// are counted, even if the grid was read further into the art around them.
+    pub fn corners(&self) -> [(f32, f32); 4] {
+        let (width, height) = self
+            .payload
+            .encoded_dimensions()
+            .unwrap_or((self.payload.width, self.payload.height));
+        let (width, height) = (width as f32, height as f32);
+        [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)]
+            .map(|(x, y)| self.grid.to_image(x, y))
+    }
+}
+
+/// Dim a pixel to a dark gray, for drawing what lies outside a grid.
+pub(crate) fn grayed_out(pixel: Pixel) -> Pixel {
+    let gray = ((pixel.r as u32 + pixel.g as u32 + pixel.b as u32) / 6) as u8;
+    Pixel {
+        r: gray,
+        g: gray,
+        b: gray,
+        a: u8::MAX,
+    }
+}
+
+/// Color a winning share from red, through yellow, to green.
+fn heat(share: f32) -> Pixel {
+    let share = share.clamp(0.0, 1.0);
+    Pixel {
+        r: (((1.0 - share) * 2.0).min(1.0) * 255.0).round() as u8,
+        g: ((share * 2.0).min(1.0) * 255.0).round() as u8,
+        b: 0,
+        a: u8::MAX,
+    }
+}
+
+impl<P: AsRef<[Pixel]>> Image<P> {
+    /// Draw how sure a reading was of each superpixel over this image, which it must have been
+    /// read from.  Superpixels go from green when every sampled pixel agreed to red when they
+    /// were split, and everything outside the grid is grayed out.
+    pub fn confidence_heatmap(&self, reading: &Reading) -> Image {
+        let inverse = reading.grid.transform().inverse();
+        let (width, height) = self.dimensions;
+        let mut pixels = Vec::with_capacity(self.pixels().len());
+        for y in 0..height {
+            for x in 0..width {
+                let cell = inverse
+                    .map(|inverse| inverse.apply(x as f64 + 0.5, y as f64 + 0.5))
+                    .filter(|&(x, y)| x >= 0.0 && y >= 0.0)
+                    .and_then(|(x, y)| reading.confidence.get(x as u32, y as u32));
+                pixels.push(match cell {
+                    Some(confidence) => heat(confidence.share),
+                    None => grayed_out(self.pixels()[(y * width + x) as usize]),
+                });
+            }
+        }
+        Image::new(self.dimensions, pixels)
+    }
+}
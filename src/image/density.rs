@@ -0,0 +1,179 @@
+use super::Superpixel;
+
+/// Number of superpixels in the density marker.
+pub(crate) const MARKER_LEN: usize = 4;
+
+/// How many bits each superpixel carries, which trades capacity against robustness.
+///
+/// Every density splits each color channel into repeating periods, and each period into evenly
+/// sized bands, with the band a channel falls in giving its bits.  Fewer, wider bands survive
+/// more damage.  The density is marked in black and white superpixels right after the layout's
+/// patterns, so a reader can tell which one to use before reading anything else.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Density {
+    /// 3 bits, 1 per channel.  Colors move further, but survive heavy recompression.
+    Robust,
+    /// 6 bits, 2 per channel.
+    #[default]
+    Standard,
+    /// 9 bits, 3 per channel.  Only suitable for lossless images.
+    Dense,
+    /// 12 bits, 4 per channel.  Only suitable for lossless images.
+    Densest,
+    /// 2 bits, held in luminance alone.  Survives conversion to grayscale and quantization to a
+    /// small palette, like GIFs and indexed PNGs, which mix up the color channels.
+    Grayscale,
+}
+
+impl Density {
+    /// Every density, in the order a reader checks them.
+    const ALL: [Density; 5] = [
+        Density::Robust,
+        Density::Standard,
+        Density::Dense,
+        Density::Densest,
+        Density::Grayscale,
+    ];
+
+    pub fn bits_per_channel(self) -> u32 {
+        match self {
+            Density::Robust => 1,
+            Density::Standard => 2,
+            Density::Dense => 3,
+            Density::Densest => 4,
+            Density::Grayscale => 2,
+        }
+    }
+
+    /// Number of channels carrying bits.  The grayscale density only has luminance.
+    pub fn channels(self) -> u32 {
+        match self {
+            Density::Grayscale => 1,
+            _ => 3,
+        }
+    }
+
+    /// Bits carried by each superpixel.
+    pub fn bits(self) -> u32 {
+        self.bits_per_channel() * self.channels()
+    }
+
+    /// Whether values are held in luminance rather than in each color channel.
+    pub fn is_grayscale(self) -> bool {
+        self.channels() == 1
+    }
+
+    /// Number of bands in each period of a channel.
+    pub(crate) fn levels(self) -> u16 {
+        1 << self.bits_per_channel()
+    }
+
+    /// Length of the repeating period of channel values.  The robust density uses a longer one,
+    /// so that its bands are wider.
+    pub(crate) fn period(self) -> u16 {
+        match self {
+            Density::Robust | Density::Grayscale => 128,
+            _ => 64,
+        }
+    }
+
+    /// Width of a single band of channel values.
+    pub(crate) fn band(self) -> u16 {
+        self.period() / self.levels()
+    }
+
+    /// The black and white superpixels marking this density.  Any two markers differ in at least
+    /// two superpixels.
+    pub(crate) fn marker(self) -> [Superpixel; MARKER_LEN] {
+        use Superpixel::{Black as B, White as W};
+        match self {
+            Density::Robust => [B, B, W, W],
+            Density::Standard => [B, W, B, W],
+            Density::Dense => [B, W, W, B],
+            Density::Densest => [W, B, B, W],
+            Density::Grayscale => [W, W, B, B],
+        }
+    }
+
+    /// Find the density with the given marker.
+    pub(crate) fn from_marker(marker: &[Superpixel]) -> Option<Density> {
+        Density::ALL
+            .iter()
+            .copied()
+            .find(|density| density.marker()[..] == *marker)
+    }
+
+    /// The value a superpixel holds at this density.  Black and white hold all zeros and all
+    /// ones.
+    pub(crate) fn value(self, superpixel: Superpixel) -> u16 {
+        let mask = (1 << self.bits()) - 1;
+        match superpixel {
+            Superpixel::Black => 0,
+            Superpixel::White => mask,
+            Superpixel::Value(value) => value & mask,
+        }
+    }
+
+    /// Split bytes into values of this density's width, most significant bit first.  The last
+    /// value is padded with zeros, which doesn't matter because of the length prefix.
+    pub(crate) fn bytes_to_values(self, bytes: &[u8]) -> Vec<u16> {
+        let bits = self.bits();
+        let mut values = Vec::with_capacity((bytes.len() * 8).div_ceil(bits as usize));
+        let mut buffer = 0u32;
+        let mut buffered = 0;
+        for &byte in bytes {
+            buffer = (buffer << 8) | byte as u32;
+            buffered += 8;
+            while buffered >= bits {
+                buffered -= bits;
+                values.push((buffer >> buffered) as u16 & ((1 << bits) - 1));
+            }
+        }
+        if buffered > 0 {
+            values.push((buffer << (bits - buffered)) as u16 & ((1 << bits) - 1));
+        }
+        values
+    }
+
+    /// Join values of this density's width back into bytes, dropping any trailing partial byte.
+    pub(crate) fn values_to_bytes(self, values: &[u16]) -> Vec<u8> {
+        let bits = self.bits();
+        let mut bytes = Vec::with_capacity(values.len() * bits as usize / 8);
+        let mut buffer = 0u32;
+        let mut buffered = 0;
+        for &value in values {
+            buffer = (buffer << bits) | (value as u32 & ((1 << bits) - 1));
+            buffered += bits;
+            while buffered >= 8 {
+                buffered -= 8;
+                bytes.push((buffer >> buffered) as u8);
+            }
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packing_roundtrip() {
+        let bytes: Vec<u8> = (0..=255).rev().collect();
+        for &density in &Density::ALL {
+            let values = density.bytes_to_values(&bytes);
+            assert_eq!(
+                values.len(),
+                (bytes.len() * 8).div_ceil(density.bits() as usize)
+            );
+            assert!(values.iter().all(|&value| value < 1 << density.bits()));
+            assert_eq!(density.values_to_bytes(&values), bytes, "{:?}", density);
+        }
+
+        // The standard density packs 3 bytes into 4 values, like base64.
+        assert_eq!(
+            Density::Standard.bytes_to_values(b"Man"),
+            vec![19, 22, 5, 46]
+        );
+    }
+}
@@ -5,7 +5,31 @@ pub enum Error {
     NoTargetFound,
     SuperpixelGridNotSquare,
     InvalidDimensions,
-    InvalidLength { encoded: u16, available: u16 },
+    InvalidLength {
+        encoded: u32,
+        available: u32,
+    },
+    PayloadTooLarge,
+    ImageTooSmall,
+    UnknownErrorCorrection(u8),
+    UnknownDensity,
+    TooManyErrors,
+    NoHeader,
+    UnsupportedVersion(u8),
+    UnsupportedFlags(u8),
+    UnsupportedCompression(u8),
+    UnsupportedCodec(u8),
+    /// The data was read without errors that could be detected, but doesn't match the checksum
+    /// it was written with, so the image is damaged beyond repair.
+    ChecksumMismatch,
+    /// The rectangle a payload was to be baked into is empty or reaches outside the image.
+    PlacementOutsideImage,
+    /// The header says the data is signed, but it is too short to hold a signature.
+    MissingSignature,
+    /// A payload spread over several frames is missing the frame with this index.
+    MissingFrame(u16),
+    /// Frames being joined into one payload don't agree on how it was spread over them.
+    MismatchedFrames,
 }
 
 impl fmt::Display for Error {
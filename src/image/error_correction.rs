@@ -0,0 +1,115 @@
+use super::reed_solomon;
+use super::Error;
+use std::convert::TryFrom;
+
+/// Amount of redundancy added to payload data, modeled after the QR code levels.
+///
+/// Data is split into Reed-Solomon blocks of at most 255 bytes, and each level notes the rough
+/// share of damaged bytes in a block that can be repaired.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub enum ErrorCorrection {
+    /// Roughly 7% of bytes can be repaired.
+    Low,
+    /// Roughly 15% of bytes can be repaired.
+    #[default]
+    Medium,
+    /// Roughly 25% of bytes can be repaired.
+    Quartile,
+    /// Roughly 30% of bytes can be repaired.
+    High,
+}
+
+impl From<ErrorCorrection> for u8 {
+    fn from(level: ErrorCorrection) -> u8 {
+        match level {
+            ErrorCorrection::Low => 0,
+            ErrorCorrection::Medium => 1,
+            ErrorCorrection::Quartile => 2,
+            ErrorCorrection::High => 3,
+        }
+    }
+}
+
+impl TryFrom<u8> for ErrorCorrection {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(ErrorCorrection::Low),
+            1 => Ok(ErrorCorrection::Medium),
+            2 => Ok(ErrorCorrection::Quartile),
+            3 => Ok(ErrorCorrection::High),
+            value => Err(Error::UnknownErrorCorrection(value)),
+        }
+    }
+}
+
+impl ErrorCorrection {
+    /// Parity bytes per data byte, as (numerator, denominator).
+    fn parity_ratio(self) -> (usize, usize) {
+        match self {
+            ErrorCorrection::Low => (3, 20),
+            ErrorCorrection::Medium => (3, 7),
+            ErrorCorrection::Quartile => (1, 1),
+            ErrorCorrection::High => (3, 2),
+        }
+    }
+
+    /// Parity bytes needed for a block with this many data bytes.
+    fn parity_len(self, data_len: usize) -> usize {
+        let (numerator, denominator) = self.parity_ratio();
+        (data_len * numerator).div_ceil(denominator).max(2)
+    }
+
+    /// The most data bytes that fit in a single block along with their parity.
+    fn max_block_data(self) -> usize {
+        let (numerator, denominator) = self.parity_ratio();
+        255 * denominator / (numerator + denominator)
+    }
+
+    /// Sizes of the blocks that data of the given length is split into, as (data, parity).
+    /// Data is spread as evenly as possible, so no block is much weaker than another.
+    fn blocks(self, length: usize) -> impl Iterator<Item = (usize, usize)> {
+        let max = self.max_block_data();
+        let count = length.div_ceil(max);
+        let base = length.checked_div(count).unwrap_or(0);
+        let extra = length.checked_rem(count).unwrap_or(0);
+        (0..count).map(move |i| {
+            let data_len = if i < extra { base + 1 } else { base };
+            (data_len, self.parity_len(data_len))
+        })
+    }
+
+    /// Total length of the protected data, parity included.
+    pub(crate) fn protected_len(self, length: usize) -> usize {
+        self.blocks(length)
+            .map(|(data_len, parity_len)| data_len + parity_len)
+            .sum()
+    }
+
+    /// Split the data into blocks, each followed by its parity.
+    pub(crate) fn protect(self, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(self.protected_len(data.len()));
+        let mut remaining = data;
+        for (data_len, parity_len) in self.blocks(data.len()) {
+            let (block, rest) = remaining.split_at(data_len);
+            output.extend_from_slice(block);
+            output.extend(reed_solomon::encode(block, parity_len));
+            remaining = rest;
+        }
+        output
+    }
+
+    /// Repair protected data of the given original length in place, returning the data.
+    pub(crate) fn repair(self, protected: &mut [u8], length: usize) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::with_capacity(length);
+        let mut offset = 0;
+        for (data_len, parity_len) in self.blocks(length) {
+            let block = &mut protected[offset..offset + data_len + parity_len];
+            reed_solomon::correct(block, parity_len)?;
+            output.extend_from_slice(&block[..data_len]);
+            offset += data_len + parity_len;
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,346 @@
+//! Finding grids laid out with QR-style finder patterns, which may be skewed or seen in
+//! perspective.
+//!
+//! The image is split into dark and light by brightness alone, because a photo rarely has true
+//! black or white in it.  Each row is scanned for the dark, light, dark, light, dark runs in a
+//! 1:1:3:1:1 ratio that a line through the middle of a finder pattern crosses, and hits are
+//! checked again vertically and diagonally.  Any three finder centers that form a rough right
+//! angle give the grid's corners and, along with the size of their superpixels, its width.  The
+//! alignment marker near the fourth corner is then looked for close to where those three corners
+//! put it, which pins down the perspective.
+
+use super::homography::Homography;
+use super::layout::Layout;
+use super::{Grid, Image, Pixel};
+
+/// Fraction of a superpixel skipped on each side when reading a fitted grid, because it only
+/// roughly lines up with the pixels.
+const FITTED_MARGIN: f32 = 0.2;
+
+/// How many of the most often seen finder patterns are tried in combination.
+const MAX_FINDERS: usize = 8;
+
+fn dark(pixel: Pixel) -> bool {
+    // Rec. 601 luma, scaled by 1000
+    pixel.r as u32 * 299 + pixel.g as u32 * 587 + pixel.b as u32 * 114 < 128_000
+}
+
+/// Check five run lengths, alternating dark and light and starting with dark, against a pattern
+/// whose center run is the given number of superpixels wide and whose other runs are one
+/// superpixel wide, returning the superpixel size.
+///
+/// The outer runs only need to be long enough, because they can blend into whatever is beside
+/// the pattern.
+fn module_size(runs: [u32; 5], center: f32) -> Option<f32> {
+    let module = (runs[1] + runs[2] + runs[3]) as f32 / (center + 2.0);
+    let close =
+        |len: u32, expected: f32| (len as f32 - expected * module).abs() <= expected * module * 0.5;
+    if module >= 1.0
+        && close(runs[1], 1.0)
+        && close(runs[2], center)
+        && close(runs[3], 1.0)
+        && runs[0] as f32 >= module * 0.5
+        && runs[4] as f32 >= module * 0.5
+    {
+        Some(module)
+    } else {
+        None
+    }
+}
+
+/// A pattern center in image coordinates, with its superpixel size.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Found {
+    x: f64,
+    y: f64,
+    module: f64,
+    count: u32,
+}
+
+impl Found {
+    fn distance(&self, other: &Found) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+}
+
+/// Dark and light runs along a line of pixels, as (dark, start, length).
+fn runs<I: Iterator<Item = bool>>(pixels: I) -> Vec<(bool, u32, u32)> {
+    let mut runs: Vec<(bool, u32, u32)> = Vec::new();
+    for (i, dark) in pixels.enumerate() {
+        match runs.last_mut() {
+            Some(run) if run.0 == dark => run.2 += 1,
+            _ => runs.push((dark, i as u32, 1)),
+        }
+    }
+    runs
+}
+
+/// Windows of five runs that match a pattern with the given center width, as the middle of the
+/// center run and the superpixel size.
+fn matches(runs: &[(bool, u32, u32)], center: f32) -> impl Iterator<Item = (f64, f32)> + '_ {
+    runs.windows(5).filter_map(move |window| {
+        if !window[0].0 {
+            return None;
+        }
+        let lengths = [
+            window[0].2,
+            window[1].2,
+            window[2].2,
+            window[3].2,
+            window[4].2,
+        ];
+        module_size(lengths, center)
+            .map(|module| (window[2].1 as f64 + window[2].2 as f64 / 2.0, module))
+    })
+}
+
+impl<P: AsRef<[Pixel]>> Image<P> {
+    fn dark_at(&self, x: i64, y: i64) -> Option<bool> {
+        if x < 0 || y < 0 || x >= self.dimensions.0 as i64 || y >= self.dimensions.1 as i64 {
+            None
+        } else {
+            Some(dark(
+                self.pixels()[y as usize * self.dimensions.0 as usize + x as usize],
+            ))
+        }
+    }
+
+    /// Lengths of the dark run containing the start, the light run after it, and the dark run
+    /// after that, walking in the given direction.  No run is followed past the limit.
+    fn walk(&self, start: (i64, i64), step: (i64, i64), limit: u32) -> [u32; 3] {
+        let mut lens = [0u32; 3];
+        let mut position = start;
+        for (i, len) in lens.iter_mut().enumerate() {
+            let want_dark = i != 1;
+            while *len < limit && self.dark_at(position.0, position.1) == Some(want_dark) {
+                *len += 1;
+                position = (position.0 + step.0, position.1 + step.1);
+            }
+        }
+        lens
+    }
+
+    /// Check for a pattern along a line through the given point, returning how many steps along
+    /// the line its center is from the point, and the superpixel size in steps.
+    fn cross_check(
+        &self,
+        point: (i64, i64),
+        step: (i64, i64),
+        center: f32,
+        limit: u32,
+    ) -> Option<(f64, f32)> {
+        let forward = self.walk(point, step, limit);
+        let backward = self.walk(point, (-step.0, -step.1), limit);
+        if forward[0] == 0 {
+            return None;
+        }
+        let lengths = [
+            backward[2],
+            backward[1],
+            forward[0] + backward[0] - 1,
+            forward[1],
+            forward[2],
+        ];
+        module_size(lengths, center)
+            .map(|module| ((forward[0] as f64 - backward[0] as f64) / 2.0, module))
+    }
+
+    /// Confirm a pattern seen in a row, checking it vertically, horizontally again, and
+    /// diagonally, and returning its refined center.
+    fn confirm(&self, x: f64, y: u32, module: f32, center: f32) -> Option<Found> {
+        let limit = (module * (center + 4.0) * 2.0).ceil() as u32;
+        let x = x.floor() as i64;
+        let (offset_y, vertical) = self.cross_check((x, y as i64), (0, 1), center, limit)?;
+        let y = y as f64 + offset_y;
+        let (offset_x, horizontal) =
+            self.cross_check((x, y.floor() as i64), (1, 0), center, limit)?;
+        let x = x as f64 + offset_x;
+        self.cross_check((x.floor() as i64, y.floor() as i64), (1, 1), center, limit)?;
+
+        // Pixel centers are half a pixel in from their corners.
+        Some(Found {
+            x: x + 0.5,
+            y: y + 0.5,
+            module: (vertical + horizontal) as f64 / 2.0,
+            count: 1,
+        })
+    }
+
+    /// Every finder pattern in the image, most often seen first.
+    fn finder_patterns(&self) -> Vec<Found> {
+        let (width, height) = self.dimensions;
+        let mut found: Vec<Found> = Vec::new();
+        for y in 0..height {
+            let row =
+                &self.pixels()[y as usize * width as usize..(y as usize + 1) * width as usize];
+            let runs = runs(row.iter().map(|&pixel| dark(pixel)));
+            for (x, module) in matches(&runs, 3.0) {
+                let pattern = match self.confirm(x, y, module, 3.0) {
+                    Some(pattern) => pattern,
+                    None => continue,
+                };
+
+                // The same pattern shows up in every row through its center, so nearby hits are
+                // merged.
+                match found.iter_mut().find(|other| {
+                    other.distance(&pattern) < other.module * 2.0
+                        && other.module < pattern.module * 2.0
+                        && pattern.module < other.module * 2.0
+                }) {
+                    Some(other) => {
+                        let count = other.count as f64;
+                        other.x = (other.x * count + pattern.x) / (count + 1.0);
+                        other.y = (other.y * count + pattern.y) / (count + 1.0);
+                        other.module = (other.module * count + pattern.module) / (count + 1.0);
+                        other.count += 1;
+                    }
+                    None => found.push(pattern),
+                }
+            }
+        }
+
+        found.retain(|pattern| pattern.count >= 2);
+        found.sort_by_key(|pattern| std::cmp::Reverse(pattern.count));
+        found.truncate(MAX_FINDERS);
+        found
+    }
+
+    /// Look for the alignment marker around where it is expected, returning the closest few
+    /// places it might be.
+    fn alignments_near(&self, expected: (f64, f64), module: f64) -> Vec<(f64, f64)> {
+        let radius = (module * 4.0).ceil() as i64;
+        let (center_x, center_y) = (expected.0.floor() as i64, expected.1.floor() as i64);
+        let left = (center_x - radius).max(0);
+        let right = (center_x + radius).min(self.dimensions.0 as i64 - 1);
+        let top = (center_y - radius).max(0);
+        let bottom = (center_y + radius).min(self.dimensions.1 as i64 - 1);
+
+        let mut found: Vec<Found> = Vec::new();
+        for y in top..=bottom {
+            let runs = runs((left..=right).map(|x| self.dark_at(x, y) == Some(true)));
+            for (x, found_module) in matches(&runs, 1.0) {
+                if (found_module as f64) < module * 0.5 || found_module as f64 > module * 2.0 {
+                    continue;
+                }
+                let pattern = match self.confirm(left as f64 + x, y as u32, found_module, 1.0) {
+                    Some(pattern) => pattern,
+                    None => continue,
+                };
+                if !found
+                    .iter()
+                    .any(|other| other.distance(&pattern) < module / 2.0)
+                {
+                    found.push(pattern);
+                }
+            }
+        }
+
+        let distance = |pattern: &Found| (pattern.x - expected.0).hypot(pattern.y - expected.1);
+        found.sort_by(|a, b| {
+            distance(a)
+                .partial_cmp(&distance(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        found
+            .into_iter()
+            .take(3)
+            .map(|pattern| (pattern.x, pattern.y))
+            .collect()
+    }
+
+    /// Grids that can be fitted to three finder patterns, with the first one given as the corner
+    /// between the other two.  Grids using each likely alignment marker and one assuming no
+    /// perspective are returned, for a few widths around the estimated one.
+    fn fit_finders(&self, corner: Found, across: Found, down: Found) -> Vec<Grid> {
+        // Superpixel sizes were measured along the image's axes, which cut across a rotated
+        // pattern at an angle and so see it wider than it is.
+        let (dx, dy) = (across.x - corner.x, across.y - corner.y);
+        let slant = dx.abs().max(dy.abs()) / dx.hypot(dy);
+        let module = (corner.module + across.module + down.module) / 3.0 * slant;
+        let estimate = (corner.distance(&across) + corner.distance(&down)) / 2.0 / module + 7.0;
+        let estimate = estimate.round() as i64;
+
+        let mut grids = Vec::new();
+        for offset in &[0, -1, 1, -2, 2] {
+            let width = estimate + offset;
+            if width < Layout::Finders.min_width() as i64 {
+                continue;
+            }
+            let width = width as u32;
+            let span = width as f64 - 7.0;
+            let step_across = ((across.x - corner.x) / span, (across.y - corner.y) / span);
+            let step_down = ((down.x - corner.x) / span, (down.y - corner.y) / span);
+            let origin = (
+                corner.x - 3.5 * (step_across.0 + step_down.0),
+                corner.y - 3.5 * (step_across.1 + step_down.1),
+            );
+            let affine = Homography::affine(origin, step_across, step_down);
+
+            let alignment = width as f64 - 6.5;
+            for found in self.alignments_near(affine.apply(alignment, alignment), module) {
+                let far = width as f64 - 3.5;
+                let from = [(3.5, 3.5), (far, 3.5), (3.5, far), (alignment, alignment)];
+                let to = [
+                    (corner.x, corner.y),
+                    (across.x, across.y),
+                    (down.x, down.y),
+                    found,
+                ];
+                if let Some(homography) = Homography::from_points(&from, &to) {
+                    grids.push(Grid::fitted(homography, width, FITTED_MARGIN));
+                }
+            }
+            grids.push(Grid::fitted(affine, width, FITTED_MARGIN));
+        }
+        grids
+    }
+
+    /// All the grids that finder patterns in the image could belong to, best guesses first.
+    pub(crate) fn finder_candidates(&self) -> Vec<Grid> {
+        let found = self.finder_patterns();
+        let mut candidates = Vec::new();
+
+        for i in 0..found.len() {
+            for j in i + 1..found.len() {
+                for k in j + 1..found.len() {
+                    let triple = [found[i], found[j], found[k]];
+
+                    // The corner is the pattern with the squarest angle to the other two.
+                    let angle = |corner: usize| {
+                        let (a, b) = (triple[(corner + 1) % 3], triple[(corner + 2) % 3]);
+                        let c = triple[corner];
+                        let dot = (a.x - c.x) * (b.x - c.x) + (a.y - c.y) * (b.y - c.y);
+                        (dot / (c.distance(&a) * c.distance(&b))).abs()
+                    };
+                    let corner = (0..3)
+                        .min_by(|&a, &b| {
+                            angle(a)
+                                .partial_cmp(&angle(b))
+                                .unwrap_or(std::cmp::Ordering::Equal)
+                        })
+                        .unwrap_or(0);
+                    if angle(corner) > 0.5 {
+                        continue;
+                    }
+                    let (c, a, b) = (
+                        triple[corner],
+                        triple[(corner + 1) % 3],
+                        triple[(corner + 2) % 3],
+                    );
+                    let ratio = c.distance(&a) / c.distance(&b);
+                    if !(0.5..=2.0).contains(&ratio) {
+                        continue;
+                    }
+
+                    // Unmirrored grids turn clockwise from across to down, because image y goes
+                    // down.  Mirrored ones are tried after.
+                    let clockwise = (a.x - c.x) * (b.y - c.y) - (a.y - c.y) * (b.x - c.x) > 0.0;
+                    let (across, down) = if clockwise { (a, b) } else { (b, a) };
+                    candidates.extend(self.fit_finders(c, across, down));
+                    candidates.extend(self.fit_finders(c, down, across));
+                }
+            }
+        }
+        candidates
+    }
+}
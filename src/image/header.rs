@@ -0,0 +1,308 @@
+use super::reed_solomon;
+use super::{Error, ErrorCorrection};
+use std::convert::{TryFrom, TryInto};
+
+/// Marks the start of a payload, right after the target.
+const MAGIC: [u8; 2] = *b"IM";
+
+/// The current payload format version.  Anything else is rejected rather than misread.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// The lead holds the magic, the version, and the length of the header body that follows it.
+/// It is always the same size, so that any version can at least be identified.
+const LEAD_DATA_LEN: usize = 4;
+const LEAD_PARITY_LEN: usize = 4;
+pub(crate) const LEAD_LEN: usize = LEAD_DATA_LEN + LEAD_PARITY_LEN;
+
+/// The header body of this version: flags, compression, codec, error correction, length, and
+/// checksum.
+const BODY_DATA_LEN: usize = 12;
+
+/// How much a frame adds to the header body: the frame's index and the frame count.
+const FRAME_LEN: usize = 4;
+
+/// How much a grid that isn't square adds to the header body: its columns and rows.
+const GRID_LEN: usize = 4;
+
+/// Parity for a header body, which is protected more heavily than the data because nothing can
+/// be read without it.
+fn body_parity_len(body_len: usize) -> usize {
+    body_len.max(4)
+}
+
+/// Compression applied to the payload data, recorded so readers know how to undo it.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl From<Compression> for u8 {
+    fn from(compression: Compression) -> u8 {
+        match compression {
+            Compression::None => 0,
+            Compression::Gzip => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for Compression {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Gzip),
+            value => Err(Error::UnsupportedCompression(value)),
+        }
+    }
+}
+
+/// How the decompressed payload data is meant to be interpreted.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Codec {
+    /// Opaque bytes.
+    #[default]
+    Raw,
+    /// A bincode-serialized song.
+    Bincode,
+}
+
+impl From<Codec> for u8 {
+    fn from(codec: Codec) -> u8 {
+        match codec {
+            Codec::Raw => 0,
+            Codec::Bincode => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(Codec::Raw),
+            1 => Ok(Codec::Bincode),
+            value => Err(Error::UnsupportedCodec(value)),
+        }
+    }
+}
+
+/// Feature flags for optional parts of the format.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub struct Flags(u8);
+
+impl Flags {
+    /// The data was encrypted with a passphrase after it was compressed, so it has to be
+    /// decrypted before it can be decompressed.
+    pub const ENCRYPTED: Flags = Flags(1);
+
+    /// The data starts with a signature over the rest of it.
+    pub const SIGNED: Flags = Flags(2);
+
+    /// The data is one frame of a longer payload, and the header goes on to say which.
+    pub const FRAMED: Flags = Flags(4);
+
+    /// The bytes after the header are scattered across the payload in an order seeded from its
+    /// checksum, so that damage to one patch of an image is spread out over every error
+    /// correction block.
+    pub const INTERLEAVED: Flags = Flags(8);
+
+    /// The grid isn't square, and the header goes on to give its columns and rows.
+    pub const RECTANGULAR: Flags = Flags(16);
+
+    /// Every flag this version knows how to handle.
+    const KNOWN: u8 = Flags::ENCRYPTED.0
+        | Flags::SIGNED.0
+        | Flags::FRAMED.0
+        | Flags::INTERLEAVED.0
+        | Flags::RECTANGULAR.0;
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Flags) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Flags) {
+        self.0 &= !other.0;
+    }
+}
+
+impl TryFrom<u8> for Flags {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        if value & !Flags::KNOWN != 0 {
+            Err(Error::UnsupportedFlags(value & !Flags::KNOWN))
+        } else {
+            Ok(Flags(value))
+        }
+    }
+}
+
+/// Where a payload spread over the frames of an animation sits among them.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub struct Frame {
+    /// Which frame this is, counting from 0.
+    pub index: u16,
+    /// How many frames the payload is spread over.
+    pub count: u16,
+}
+
+/// Describes how the payload data was produced, so that readers know how to handle it.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct Header {
+    pub flags: Flags,
+    pub compression: Compression,
+    pub codec: Codec,
+    pub error_correction: ErrorCorrection,
+
+    /// Which frame this is, if the payload is spread over several.  The header is flagged as
+    /// framed when this is given.
+    pub frame: Option<Frame>,
+
+    /// The columns and rows of the grid the payload is laid out in, if it isn't square.  This is
+    /// filled in when the payload is laid out, and the header is flagged as rectangular when it
+    /// is given.
+    pub grid: Option<(u16, u16)>,
+}
+
+/// A header read out of a payload, along with what it says about the data that follows it.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub(crate) struct Parsed {
+    pub header: Header,
+    /// Length of the data, before error correction.
+    pub length: u32,
+    /// CRC32 of the data.
+    pub checksum: u32,
+    /// Number of bytes the header took up.
+    pub header_len: usize,
+}
+
+impl Header {
+    /// Length of the header body, before its parity.
+    fn body_len(&self) -> usize {
+        BODY_DATA_LEN + self.frame.map_or(0, |_| FRAME_LEN) + self.grid.map_or(0, |_| GRID_LEN)
+    }
+
+    /// Number of bytes this header takes up once encoded, parity included.
+    pub(crate) fn encoded_len(&self) -> usize {
+        LEAD_LEN + self.body_len() + body_parity_len(self.body_len())
+    }
+
+    /// Serialize the lead and header body, each with their parity.
+    pub(crate) fn encode(&self, length: u32, checksum: u32) -> Vec<u8> {
+        let mut flags = self.flags;
+        if self.frame.is_some() {
+            flags.insert(Flags::FRAMED);
+        }
+        if self.grid.is_some() {
+            flags.insert(Flags::RECTANGULAR);
+        }
+        let body_len = self.body_len();
+
+        let mut lead = Vec::with_capacity(LEAD_LEN);
+        lead.extend_from_slice(&MAGIC);
+        lead.push(FORMAT_VERSION);
+        lead.push(body_len as u8);
+        lead.extend(reed_solomon::encode(&lead, LEAD_PARITY_LEN));
+
+        let mut body = Vec::with_capacity(body_len);
+        body.push(flags.bits());
+        body.push(self.compression.into());
+        body.push(self.codec.into());
+        body.push(self.error_correction.into());
+        body.extend_from_slice(&length.to_be_bytes());
+        body.extend_from_slice(&checksum.to_be_bytes());
+        if let Some(frame) = self.frame {
+            body.extend_from_slice(&frame.index.to_be_bytes());
+            body.extend_from_slice(&frame.count.to_be_bytes());
+        }
+        if let Some((columns, rows)) = self.grid {
+            body.extend_from_slice(&columns.to_be_bytes());
+            body.extend_from_slice(&rows.to_be_bytes());
+        }
+        body.extend(reed_solomon::encode(&body, body_parity_len(body_len)));
+
+        lead.extend(body);
+        lead
+    }
+
+    /// Repair and parse a header in place.
+    pub(crate) fn decode(bytes: &mut [u8]) -> Result<Parsed, Error> {
+        if bytes.len() < LEAD_LEN {
+            return Err(Error::InvalidLength {
+                encoded: LEAD_LEN as u32,
+                available: bytes.len() as u32,
+            });
+        }
+
+        // A lead that can't be repaired is treated the same as a missing one, because old
+        // images from before the header existed will land here.
+        let lead = &mut bytes[..LEAD_LEN];
+        if reed_solomon::correct(lead, LEAD_PARITY_LEN).is_err() || lead[..2] != MAGIC {
+            return Err(Error::NoHeader);
+        }
+        if lead[2] != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(lead[2]));
+        }
+        let body_len = lead[3] as usize;
+        if body_len < BODY_DATA_LEN || body_len + body_parity_len(body_len) > 255 {
+            return Err(Error::NoHeader);
+        }
+
+        let header_len = LEAD_LEN + body_len + body_parity_len(body_len);
+        if bytes.len() < header_len {
+            return Err(Error::InvalidLength {
+                encoded: header_len as u32,
+                available: bytes.len() as u32,
+            });
+        }
+        let body = &mut bytes[LEAD_LEN..header_len];
+        reed_solomon::correct(body, body_parity_len(body_len))?;
+
+        let flags = Flags::try_from(body[0])?;
+        // Optional parts follow the fixed ones in the order of their flags, each a pair of u16s.
+        let mut pairs = body[BODY_DATA_LEN..body_len].chunks_exact(4).map(|pair| {
+            (
+                u16::from_be_bytes(pair[..2].try_into().unwrap()),
+                u16::from_be_bytes(pair[2..].try_into().unwrap()),
+            )
+        });
+        let mut pair = |flag| {
+            if flags.contains(flag) {
+                pairs.next().map(Some).ok_or(Error::NoHeader)
+            } else {
+                Ok(None)
+            }
+        };
+        let frame = pair(Flags::FRAMED)?.map(|(index, count)| Frame { index, count });
+        let grid = pair(Flags::RECTANGULAR)?;
+        let header = Header {
+            flags,
+            compression: Compression::try_from(body[1])?,
+            codec: Codec::try_from(body[2])?,
+            error_correction: ErrorCorrection::try_from(body[3])?,
+            frame,
+            grid,
+        };
+        let length = u32::from_be_bytes(body[4..8].try_into().unwrap());
+        let checksum = u32::from_be_bytes(body[8..12].try_into().unwrap());
+
+        Ok(Parsed {
+            header,
+            length,
+            checksum,
+            header_len,
+        })
+    }
+}
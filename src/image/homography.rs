@@ -0,0 +1,124 @@
+//! Projective transforms from grid coordinates to image coordinates.
+//!
+//! A homography is a 3x3 matrix acting on homogeneous coordinates.  It maps straight lines to
+//! straight lines, so it covers every way a flat grid can look in an image, whether scaled,
+//! rotated, mirrored, skewed, or seen in perspective.
+
+/// A projective transform, stored row-major with the last element fixed at 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Homography([f64; 9]);
+
+impl Homography {
+    /// A transform that keeps parallel lines parallel, given where the grid origin lands and the
+    /// steps taken along each grid axis.
+    pub fn affine(origin: (f64, f64), across: (f64, f64), down: (f64, f64)) -> Self {
+        Homography([
+            across.0, down.0, origin.0, across.1, down.1, origin.1, 0.0, 0.0, 1.0,
+        ])
+    }
+
+    /// The transform that maps each of four points to its counterpart, if no three of them lie on
+    /// a line.
+    pub fn from_points(from: &[(f64, f64); 4], to: &[(f64, f64); 4]) -> Option<Self> {
+        // Each pair gives two linear equations in the eight unknown matrix elements.
+        let mut system = [[0.0f64; 9]; 8];
+        for (i, (&(x, y), &(u, v))) in from.iter().zip(to.iter()).enumerate() {
+            system[i * 2] = [x, y, 1.0, 0.0, 0.0, 0.0, -x * u, -y * u, u];
+            system[i * 2 + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -x * v, -y * v, v];
+        }
+
+        // Gaussian elimination with partial pivoting.
+        for column in 0..8 {
+            let pivot = (column..8).max_by(|&a, &b| {
+                system[a][column]
+                    .abs()
+                    .partial_cmp(&system[b][column].abs())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })?;
+            if system[pivot][column].abs() < 1e-9 {
+                return None;
+            }
+            system.swap(column, pivot);
+            let pivot_row = system[column];
+            for (row, equation) in system.iter_mut().enumerate() {
+                if row != column {
+                    let factor = equation[column] / pivot_row[column];
+                    for (element, &pivot_element) in equation.iter_mut().zip(pivot_row.iter()) {
+                        *element -= factor * pivot_element;
+                    }
+                }
+            }
+        }
+
+        let mut matrix = [1.0; 9];
+        for (i, element) in matrix.iter_mut().take(8).enumerate() {
+            *element = system[i][8] / system[i][i];
+        }
+        Some(Homography(matrix))
+    }
+
+    /// The transform that undoes this one, if it doesn't collapse the plane.
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.0;
+        let cofactors = [
+            m[4] * m[8] - m[5] * m[7],
+            m[2] * m[7] - m[1] * m[8],
+            m[1] * m[5] - m[2] * m[4],
+            m[5] * m[6] - m[3] * m[8],
+            m[0] * m[8] - m[2] * m[6],
+            m[2] * m[3] - m[0] * m[5],
+            m[3] * m[7] - m[4] * m[6],
+            m[1] * m[6] - m[0] * m[7],
+            m[0] * m[4] - m[1] * m[3],
+        ];
+        let determinant = m[0] * cofactors[0] + m[1] * cofactors[3] + m[2] * cofactors[6];
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let mut inverse = [0.0; 9];
+        for (element, cofactor) in inverse.iter_mut().zip(cofactors.iter()) {
+            *element = cofactor / determinant;
+        }
+        Some(Homography(inverse))
+    }
+
+    /// Whether the transform keeps parallel lines parallel.
+    pub fn is_affine(&self) -> bool {
+        self.0[6] == 0.0 && self.0[7] == 0.0
+    }
+
+    /// Map a point through the transform.
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let m = &self.0;
+        let w = m[6] * x + m[7] * y + m[8];
+        (
+            (m[0] * x + m[1] * y + m[2]) / w,
+            (m[3] * x + m[4] * y + m[5]) / w,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn maps_points() {
+        let from = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0), (10.0, 10.0)];
+        let to = [(5.0, 3.0), (50.0, 10.0), (2.0, 40.0), (60.0, 70.0)];
+        let homography = Homography::from_points(&from, &to).expect("Could not fit homography");
+        for (&(x, y), &(u, v)) in from.iter().zip(to.iter()) {
+            let (mapped_x, mapped_y) = homography.apply(x, y);
+            assert!((mapped_x - u).abs() < 1e-6 && (mapped_y - v).abs() < 1e-6);
+        }
+
+        let inverse = homography.inverse().expect("Could not invert homography");
+        for (&(x, y), &(u, v)) in from.iter().zip(to.iter()) {
+            let (mapped_x, mapped_y) = inverse.apply(u, v);
+            assert!((mapped_x - x).abs() < 1e-6 && (mapped_y - y).abs() < 1e-6);
+        }
+
+        let collinear = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0)];
+        assert!(Homography::from_points(&collinear, &to).is_none());
+    }
+}
@@ -0,0 +1,66 @@
+//! Scattering of consecutive bytes across a payload, so that damage to one patch of an image
+//! lands as a few errors in many error correction blocks rather than a hole in one of them.
+
+/// A pseudo-random order of this many positions, always the same for the same seed.  This is a
+/// Fisher-Yates shuffle driven by SplitMix64, so that it never changes underneath old images.
+fn permutation(len: usize, seed: u32) -> Vec<usize> {
+    let mut state = seed as u64;
+    let mut next = move || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let mut order: Vec<usize> = (0..len).collect();
+    for i in (1..len).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+    order
+}
+
+/// Scatter bytes in place, moving the byte at each position to where the seed's order puts it.
+pub(crate) fn interleave(data: &mut [u8], seed: u32) {
+    let original = data.to_vec();
+    for (byte, position) in original.into_iter().zip(permutation(data.len(), seed)) {
+        data[position] = byte;
+    }
+}
+
+/// Gather bytes scattered by [`interleave`] with the same seed back into order, in place.
+pub(crate) fn deinterleave(data: &mut [u8], seed: u32) {
+    let scattered = data.to_vec();
+    for (byte, position) in data.iter_mut().zip(permutation(scattered.len(), seed)) {
+        *byte = scattered[position];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interleave_roundtrip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut scattered = data.clone();
+        interleave(&mut scattered, 1234);
+        assert_ne!(scattered, data);
+
+        // Neighbours end up far apart.
+        let position = |byte: u8| scattered.iter().position(|&b| b == byte).unwrap();
+        let close = (0..255)
+            .filter(|&byte| (position(byte) as isize - position(byte + 1) as isize).abs() < 4)
+            .count();
+        assert!(close < 16, "{} neighbours stayed close", close);
+
+        deinterleave(&mut scattered, 1234);
+        assert_eq!(scattered, data);
+
+        let mut other = data.clone();
+        interleave(&mut other, 4321);
+        let mut scattered = data.clone();
+        interleave(&mut scattered, 1234);
+        assert_ne!(other, scattered);
+    }
+}
@@ -0,0 +1,184 @@
+use super::{spiral_position, Superpixel, TARGET};
+
+/// Size of a finder pattern, in superpixels.
+const FINDER_SIZE: u32 = 7;
+
+/// A finder pattern along with the white separator that keeps it apart from the data.
+const FINDER_BLOCK: u32 = FINDER_SIZE + 1;
+
+/// Size of an alignment marker, in superpixels.
+const ALIGNMENT_SIZE: u32 = 5;
+
+/// How far the center of the alignment marker is from the far edges of the grid.
+const ALIGNMENT_INSET: u32 = 7;
+
+/// The fixed patterns that let a reader find the superpixel grid, and where they sit.
+///
+/// Superpixels belonging to those patterns are skipped over when laying out data, which is
+/// otherwise always in spiral order.
+#[derive(Debug, Default, Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
+pub enum Layout {
+    /// A small target in the top-left corner.  It takes up very little room, but the grid must
+    /// line up with the image's axes to be read.
+    #[default]
+    Target,
+    /// QR-style finder patterns in three corners and an alignment marker near the fourth.  These
+    /// take up a lot more room, but they let a skewed or perspective-distorted grid be read, such
+    /// as in a photo of a printed image.
+    Finders,
+}
+
+/// The superpixel at the given position of a finder block, measured from the outer corner.
+fn finder_superpixel(x: u32, y: u32) -> Superpixel {
+    if x == FINDER_SIZE || y == FINDER_SIZE {
+        return Superpixel::White;
+    }
+    match ring(x, y, FINDER_SIZE / 2) {
+        3 | 0 | 1 => Superpixel::Black,
+        _ => Superpixel::White,
+    }
+}
+
+/// The square ring around the given center that a position falls on.
+fn ring(x: u32, y: u32, center: u32) -> u32 {
+    (x.max(center) - x.min(center)).max(y.max(center) - y.min(center))
+}
+
+impl Layout {
+    /// Every layout, in the order a reader checks them.
+    pub(crate) const ALL: [Layout; 2] = [Layout::Target, Layout::Finders];
+
+    /// The smallest grid width this layout fits in.
+    pub(crate) fn min_width(self) -> u32 {
+        match self {
+            Layout::Target => 3,
+            Layout::Finders => FINDER_BLOCK * 2 + ALIGNMENT_SIZE,
+        }
+    }
+
+    /// How many superpixels are taken up by the patterns, at any width of at least the minimum.
+    fn reserved(self) -> usize {
+        match self {
+            Layout::Target => TARGET.len(),
+            Layout::Finders => {
+                (FINDER_BLOCK as usize).pow(2) * 3 + (ALIGNMENT_SIZE as usize).pow(2)
+            }
+        }
+    }
+
+    /// The narrowest grid that can hold the given number of data superpixels.
+    pub(crate) fn width_for(self, values: usize) -> u32 {
+        super::ceil_sqrt(values + self.reserved()).max(self.min_width())
+    }
+
+    /// The smallest grid following an aspect ratio that can hold the given number of data
+    /// superpixels, with the first `corner` of them in the square at its corner.  Finder patterns
+    /// are fitted assuming the grid is square, so they always get a square one, as do aspect
+    /// ratios too close to square to make a difference.
+    pub(crate) fn dimensions_for(
+        self,
+        values: usize,
+        corner: usize,
+        (across, down): (u32, u32),
+    ) -> (u32, u32) {
+        let width = self.width_for(values);
+        if self == Layout::Finders || across.min(down) == 0 {
+            return (width, width);
+        }
+        let (short, long) = (across.min(down) as u64, across.max(down) as u64);
+        let total = values + self.reserved();
+        let first = super::ceil_sqrt(corner + self.reserved()).max(self.min_width());
+        for side in first..width {
+            let length = ((side as u64 * long + short / 2) / short).min(u16::MAX as u64) as u32;
+            if side as usize * length as usize >= total {
+                return if across >= down {
+                    (length, side)
+                } else {
+                    (side, length)
+                };
+            }
+        }
+        (width, width)
+    }
+
+    /// The pattern superpixel at the given position, or None if it holds data.
+    pub(crate) fn pattern(self, width: u32, x: u32, y: u32) -> Option<Superpixel> {
+        match self {
+            Layout::Target => {
+                if x < 3 && y < 3 {
+                    // Inverse of the spiral order, within the first three rings.
+                    let index = if x >= y { x * x + y } else { y * y + 2 * y - x };
+                    Some(TARGET[index as usize])
+                } else {
+                    None
+                }
+            }
+            Layout::Finders => {
+                let far = width - FINDER_BLOCK;
+                if x < FINDER_BLOCK && y < FINDER_BLOCK {
+                    Some(finder_superpixel(x, y))
+                } else if x >= far && y < FINDER_BLOCK {
+                    Some(finder_superpixel(width - 1 - x, y))
+                } else if x < FINDER_BLOCK && y >= far {
+                    Some(finder_superpixel(x, width - 1 - y))
+                } else {
+                    let center = width - ALIGNMENT_INSET;
+                    match ring(x, y, center) {
+                        0 | 2 => Some(Superpixel::Black),
+                        1 => Some(Superpixel::White),
+                        _ => None,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the superpixels of a grid with these columns and rows, in row-major order, hold
+    /// this layout's patterns.  A few wrong superpixels are allowed in the finder patterns,
+    /// because they are large enough to be found even when damaged.
+    pub(crate) fn matches(self, (width, height): (u32, u32), data: &[Superpixel]) -> bool {
+        if width.min(height) < self.min_width() {
+            return false;
+        }
+        // Finder patterns are fitted assuming the grid is square.
+        if self == Layout::Finders && width != height {
+            return false;
+        }
+        let mismatched = match self {
+            Layout::Target => (0..TARGET.len())
+                .map(spiral_position)
+                .filter(|&(x, y)| self.pattern(width, x, y) != Some(data[(y * width + x) as usize]))
+                .count(),
+            Layout::Finders => (0..width)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .filter(|&(x, y)| match self.pattern(width, x, y) {
+                    Some(expected) => expected != data[(y * width + x) as usize],
+                    None => false,
+                })
+                .count(),
+        };
+        let allowed = match self {
+            Layout::Target => 0,
+            Layout::Finders => self.reserved() / 10,
+        };
+        mismatched <= allowed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reserved_counts() {
+        for &layout in &Layout::ALL {
+            for width in layout.min_width()..layout.min_width() + 20 {
+                let count = (0..width)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .filter(|&(x, y)| layout.pattern(width, x, y).is_some())
+                    .count();
+                assert_eq!(count, layout.reserved(), "{:?} at width {}", layout, width);
+            }
+        }
+    }
+}
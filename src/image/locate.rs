@@ -0,0 +1,426 @@
+//! Finding the superpixel grid in an image.
+//!
+//! The target is searched for anywhere in the image, in any of the eight orientations that
+//! rotating by right angles and mirroring can produce.  The first row and the first column of the
+//! target both read black, white, black, so every horizontal run of that shape is a candidate,
+//! and a matching vertical run is then looked for through either of its black ends.  Where the
+//! two meet is the corner of the grid.
+//!
+//! Grids laid out with finder patterns instead of a target are found separately, in the finder
+//! module.
+
+use super::homography::Homography;
+use super::{spiral_position, Calibration, Density, Image, Pixel, Votes, TARGET};
+use std::collections::{HashMap, HashSet};
+
+/// Coarse classification of a pixel, only used to find the target.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+enum Tone {
+    Dark,
+    Light,
+    Other,
+}
+
+fn tone(pixel: Pixel) -> Tone {
+    if pixel.is_black() {
+        Tone::Dark
+    } else if pixel.is_white() {
+        Tone::Light
+    } else {
+        Tone::Other
+    }
+}
+
+/// Where a superpixel grid sits in an image.
+///
+/// Grid coordinates are measured in superpixels from the outer corner of the target, and are
+/// mapped to image pixel coordinates through a projective transform.  Grids found from a target
+/// are only ever scaled, rotated by right angles, or mirrored, but grids fitted to finder patterns
+/// can be skewed or seen in perspective.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Grid {
+    transform: Homography,
+    columns: u32,
+    rows: u32,
+    margin: f32,
+}
+
+impl Grid {
+    /// Map a point in grid coordinates to image pixel coordinates.
+    pub fn to_image(&self, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = self.transform.apply(x as f64, y as f64);
+        (x as f32, y as f32)
+    }
+
+    /// Map a point in image pixel coordinates back to grid coordinates, if the grid is not
+    /// degenerate.
+    pub fn to_grid(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let (x, y) = self.transform.inverse()?.apply(x as f64, y as f64);
+        Some((x as f32, y as f32))
+    }
+
+    /// The number of whole superpixels that fit in the image along the grid's first axis.
+    pub fn columns(&self) -> u32 {
+        self.columns
+    }
+
+    /// The number of whole superpixels that fit in the image along the grid's second axis.
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    /// The size of the corner superpixel in image pixels, measured along the image's axes.
+    pub fn superpixel_size(&self) -> (f32, f32) {
+        let origin = self.to_image(0.0, 0.0);
+        let across = self.to_image(1.0, 0.0);
+        let down = self.to_image(0.0, 1.0);
+        (
+            (across.0 - origin.0).abs() + (down.0 - origin.0).abs(),
+            (across.1 - origin.1).abs() + (down.1 - origin.1).abs(),
+        )
+    }
+
+    /// Whether a point in image pixel coordinates lies within the first `columns` and `rows` of
+    /// superpixels of this grid.
+    pub(crate) fn covers(&self, point: (f32, f32), (columns, rows): (u32, u32)) -> bool {
+        match self.to_grid(point.0, point.1) {
+            Some((x, y)) => x >= 0.0 && y >= 0.0 && x < columns as f32 && y < rows as f32,
+            None => false,
+        }
+    }
+
+    pub(crate) fn transform(&self) -> Homography {
+        self.transform
+    }
+
+    /// The fraction of a superpixel skipped on each side when reading it.
+    pub(crate) fn margin(&self) -> f32 {
+        self.margin
+    }
+
+    /// Build a grid from a target, working out how many superpixels fit in the image.
+    fn new(
+        origin: (f32, f32),
+        across: (f32, f32),
+        down: (f32, f32),
+        dimensions: (u32, u32),
+    ) -> Self {
+        let fit = |step: (f32, f32)| {
+            fit(origin.0, step.0, dimensions.0).min(fit(origin.1, step.1, dimensions.1))
+        };
+        let widen = |(x, y): (f32, f32)| (x as f64, y as f64);
+        Grid {
+            transform: Homography::affine(widen(origin), widen(across), widen(down)),
+            columns: fit(across),
+            rows: fit(down),
+            margin: 0.0,
+        }
+    }
+
+    /// A square grid of known width fitted by a transform.
+    pub(crate) fn fitted(transform: Homography, width: u32, margin: f32) -> Self {
+        Grid {
+            transform,
+            columns: width,
+            rows: width,
+            margin,
+        }
+    }
+}
+
+/// How many steps fit between the origin and the edge of the image along one image axis.
+fn fit(origin: f32, step: f32, limit: u32) -> u32 {
+    // Allow for rounding, because origins and steps are usually whole pixels.
+    let steps = if step > 0.0 {
+        (limit as f32 - origin) / step
+    } else if step < 0.0 {
+        origin / -step
+    } else {
+        return u32::MAX;
+    };
+    (steps + 1e-3).floor().max(0.0) as u32
+}
+
+/// Longest run of in-between pixels taken as a blurred edge between black and white.
+const MAX_TRANSITION: u32 = 4;
+
+/// How far a grid's steps are refined, in pixels per superpixel.
+const REFINE_PIXELS: f32 = 1.5;
+
+/// How far the far edge of a grid moves between refinement tries, in superpixels.
+const REFINE_DRIFT: f32 = 0.25;
+
+/// A run of pixels of the same tone along a line.
+#[derive(Debug, Copy, Clone)]
+struct Run {
+    tone: Tone,
+    start: u32,
+    len: u32,
+}
+
+fn runs<I: Iterator<Item = Pixel>>(pixels: I) -> Vec<Run> {
+    let mut raw: Vec<Run> = Vec::new();
+    for (i, pixel) in pixels.enumerate() {
+        let tone = tone(pixel);
+        match raw.last_mut() {
+            Some(run) if run.tone == tone => run.len += 1,
+            _ => raw.push(Run {
+                tone,
+                start: i as u32,
+                len: 1,
+            }),
+        }
+    }
+
+    // Scaling blurs the edge between a black and a white superpixel into a few in-between
+    // pixels, which are split between the two.
+    let mut runs: Vec<Run> = Vec::with_capacity(raw.len());
+    for i in 0..raw.len() {
+        let run = raw[i];
+        let blurred_edge = run.tone == Tone::Other
+            && run.len <= MAX_TRANSITION
+            && i + 1 < raw.len()
+            && runs.last().is_some_and(|before: &Run| {
+                before.tone != Tone::Other
+                    && raw[i + 1].tone != Tone::Other
+                    && before.tone != raw[i + 1].tone
+            });
+        if blurred_edge {
+            let half = run.len / 2;
+            if let Some(before) = runs.last_mut() {
+                before.len += half;
+            }
+            raw[i + 1].start -= run.len - half;
+            raw[i + 1].len += run.len - half;
+        } else {
+            runs.push(run);
+        }
+    }
+    runs
+}
+
+/// Whether a black run is long enough to be a black superpixel next to a white one of the given
+/// length.
+fn long_enough(dark: u32, light: u32) -> bool {
+    dark * 4 >= light * 3
+}
+
+impl<P: AsRef<[Pixel]>> Image<P> {
+    /// The runs of tones down a column of pixels.
+    fn column_runs(&self, x: u32) -> Vec<Run> {
+        let width = self.dimensions.0 as usize;
+        runs((0..self.dimensions.1 as usize).map(|y| self.pixels()[y * width + x as usize]))
+    }
+
+    /// Look down a column's runs through a black superpixel containing the given row for a
+    /// vertical black, white, black run, returning the corner superpixel's outer edge and height
+    /// along with the direction the run goes.
+    fn vertical_target(&self, column: &[Run], y: u32) -> Vec<(f32, f32)> {
+        let mut found = Vec::new();
+        let index = match column.iter().position(|run| run.start + run.len > y) {
+            Some(index) if column[index].tone == Tone::Dark => index,
+            _ => return found,
+        };
+        let dark = column[index];
+        let is_target = |light: Run, far: Run| {
+            light.tone == Tone::Light
+                && far.tone == Tone::Dark
+                && long_enough(dark.len, light.len)
+                && long_enough(far.len, light.len)
+        };
+
+        // Going down
+        if let (Some(&light), Some(&far)) = (column.get(index + 1), column.get(index + 2)) {
+            if is_target(light, far) && light.start + MAX_TRANSITION >= light.len {
+                let outer = light.start.saturating_sub(light.len);
+                found.push((outer as f32, light.len as f32));
+            }
+        }
+
+        // Going up
+        if index >= 2 && is_target(column[index - 1], column[index - 2]) {
+            let light = column[index - 1];
+            let height = self.dimensions.1;
+            if light.start + light.len * 2 <= height + MAX_TRANSITION {
+                let outer = (light.start + light.len * 2).min(height);
+                found.push((outer as f32, -(light.len as f32)));
+            }
+        }
+        found
+    }
+
+    /// All the places a target seems to be, in rough scanning order.  Each corner is reported
+    /// twice, once transposed, because the target itself is symmetric along its diagonal.
+    pub(crate) fn grid_candidates(&self) -> Vec<Grid> {
+        let (width, height) = self.dimensions;
+        let mut seen = HashSet::new();
+        let mut columns = HashMap::new();
+        let mut candidates = Vec::new();
+
+        for y in 0..height {
+            let row =
+                &self.pixels()[y as usize * width as usize..(y as usize + 1) * width as usize];
+            let runs = runs(row.iter().copied());
+            for window in runs.windows(3) {
+                let (left, middle, right) = (window[0], window[1], window[2]);
+                if left.tone != Tone::Dark
+                    || middle.tone != Tone::Light
+                    || right.tone != Tone::Dark
+                    || !long_enough(left.len, middle.len)
+                    || !long_enough(right.len, middle.len)
+                {
+                    continue;
+                }
+
+                let cell = middle.len;
+
+                // The corner superpixel is at either end of the run, given as the outer edge of
+                // the corner, which way the grid goes from there, and a column through the corner.
+                // A blurred edge can leave a corner at the edge of the image a little short.
+                let mut ends = Vec::with_capacity(2);
+                if middle.start + MAX_TRANSITION >= cell {
+                    let outer = middle.start.saturating_sub(cell);
+                    ends.push((outer, 1.0, (outer + middle.start) / 2));
+                }
+                if middle.start + cell * 2 <= width + MAX_TRANSITION {
+                    let outer = (middle.start + cell * 2).min(width);
+                    ends.push((outer, -1.0, (middle.start + cell + outer) / 2));
+                }
+
+                for (outer_x, direction, x) in ends {
+                    let column = columns.entry(x).or_insert_with(|| self.column_runs(x));
+                    for (outer_y, vertical) in self.vertical_target(column, y) {
+                        let horizontal = direction * cell as f32;
+                        if !seen.insert((
+                            outer_x,
+                            outer_y as u32,
+                            horizontal as i64,
+                            vertical as i64,
+                        )) {
+                            continue;
+                        }
+                        let origin = (outer_x as f32, outer_y);
+                        let horizontal = (horizontal, 0.0);
+                        let vertical = (0.0, vertical);
+                        candidates.push((origin, horizontal, vertical));
+                        candidates.push((origin, vertical, horizontal));
+                    }
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .filter(|&(origin, across, down)| {
+                let grid = Grid::new(origin, across, down, self.dimensions);
+                grid.columns >= 3 && grid.rows >= 3 && self.has_target(&grid)
+            })
+            .map(|(origin, across, down)| Grid::new(origin, across, down, self.dimensions))
+            .collect()
+    }
+
+    /// Fine-tune the steps of a grid that keeps parallel lines parallel, leaving any other grid
+    /// as it is.
+    ///
+    /// Targets and finder patterns only measure superpixels to about a pixel, which is exact
+    /// enough for an image as it was baked, but drifts further off across the grid once the image
+    /// has been scaled.  Each step is stretched or shrunk by up to a couple of pixels, to wherever
+    /// the first row or column of superpixels reads most cleanly.  Ties go to the step closest to
+    /// the measured one.  Every try takes as many samples as the measured step would, so that
+    /// smaller steps aren't favored for having fewer samples to disagree.  Only superpixels within
+    /// the given payload columns and rows are scored.
+    pub(crate) fn refine(
+        &self,
+        grid: &Grid,
+        (width, height): (u32, u32),
+        calibration: &Calibration,
+    ) -> Grid {
+        if !grid.transform.is_affine() {
+            return *grid;
+        }
+        let origin = grid.to_image(0.0, 0.0);
+        let step = |(x, y): (f32, f32)| (x - origin.0, y - origin.1);
+        let across = step(grid.to_image(1.0, 0.0));
+        let down = step(grid.to_image(0.0, 1.0));
+        let with_steps = |across: (f32, f32), down: (f32, f32)| {
+            let widen = |(x, y): (f32, f32)| (x as f64, y as f64);
+            Grid {
+                transform: Homography::affine(widen(origin), widen(across), widen(down)),
+                ..*grid
+            }
+        };
+
+        let samples = (
+            (across.0.hypot(across.1).round() as u32).max(1),
+            (down.0.hypot(down.1).round() as u32).max(1),
+        );
+        let resolution = REFINE_DRIFT / grid.columns.max(grid.rows).max(1) as f32;
+        let best = |step: (f32, f32), score: &dyn Fn((f32, f32)) -> f32| {
+            let tries = (REFINE_PIXELS / step.0.hypot(step.1) / resolution).ceil() as i32;
+            let mut best = (score(step), step);
+            for k in (1..=tries).flat_map(|k| vec![-k, k]) {
+                let factor = 1.0 + k as f32 * resolution;
+                let stretched = (step.0 * factor, step.1 * factor);
+                let score = score(stretched);
+                if score > best.0 {
+                    best = (score, stretched);
+                }
+            }
+            best.1
+        };
+
+        // Only as many superpixels as always fit are scored, so that shorter steps aren't
+        // favored for fitting more in.
+        let counted = |len: u32, step: (f32, f32)| {
+            let size = step.0.hypot(step.1);
+            (len as f32 * size / (size + REFINE_PIXELS)).floor() as u32
+        };
+        // Past the payload, the grid may be reading whatever art it was baked into.
+        let (columns, rows) = (
+            counted(grid.columns.min(width), across),
+            counted(grid.rows.min(height), down),
+        );
+
+        let across = best(across, &|across| {
+            let grid = with_steps(across, down);
+            self.clarity(&grid, (0..columns).map(|x| (x, 0)), samples, calibration)
+        });
+        let down = best(down, &|down| {
+            let grid = with_steps(across, down);
+            self.clarity(&grid, (0..rows).map(|y| (0, y)), samples, calibration)
+        });
+        with_steps(across, down)
+    }
+
+    /// Total winning share of the given superpixels of a grid.
+    fn clarity<I: Iterator<Item = (u32, u32)>>(
+        &self,
+        grid: &Grid,
+        superpixels: I,
+        samples: (u32, u32),
+        calibration: &Calibration,
+    ) -> f32 {
+        let mut votes = Votes::default();
+        superpixels
+            .map(|(x, y)| {
+                let position = (x as f32, y as f32);
+                let density = Density::default();
+                self.sample_superpixel(grid, position, density, samples, calibration, &mut votes)
+                    .1
+                    .share
+            })
+            .sum()
+    }
+
+    /// Check the target superpixels of a candidate grid.
+    fn has_target(&self, grid: &Grid) -> bool {
+        let calibration = Calibration::default();
+        let mut votes = Votes::default();
+        TARGET.iter().enumerate().all(|(i, expected)| {
+            let position = spiral_position(i);
+            self.read_superpixel(grid, position, Density::default(), &calibration, &mut votes)
+                .0
+                == *expected
+        })
+    }
+}
@@ -0,0 +1,88 @@
+use super::{Error, Image, Pixel};
+
+/// Side of the square windows that structural similarity is measured over.
+const SSIM_WINDOW: u32 = 8;
+
+/// Stabilizing constants for structural similarity, for 8-bit values.
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Rec. 601 luma of a pixel, unrounded.
+fn luma(pixel: Pixel) -> f64 {
+    pixel.r as f64 * 0.299 + pixel.g as f64 * 0.587 + pixel.b as f64 * 0.114
+}
+
+impl<P: AsRef<[Pixel]>> Image<P> {
+    /// Peak signal-to-noise ratio of another image of the same size against this one, in
+    /// decibels, over the color channels.  Higher is closer, and identical images are infinitely
+    /// close.
+    pub fn psnr<Q: AsRef<[Pixel]>>(&self, other: &Image<Q>) -> Result<f64, Error> {
+        if self.dimensions != other.dimensions {
+            return Err(Error::InvalidDimensions);
+        }
+        let squared: f64 = self
+            .pixels()
+            .iter()
+            .zip(other.pixels())
+            .map(|(a, b)| {
+                let (r, g, b) = (
+                    a.r as f64 - b.r as f64,
+                    a.g as f64 - b.g as f64,
+                    a.b as f64 - b.b as f64,
+                );
+                r * r + g * g + b * b
+            })
+            .sum();
+        let mean = squared / (self.pixels().len() * 3).max(1) as f64;
+        Ok(10.0 * (255.0 * 255.0 / mean).log10())
+    }
+
+    /// Mean structural similarity of another image of the same size against this one, over the
+    /// luma of 8x8 windows every 4 pixels.  This is 1 for identical images and falls toward 0 as
+    /// their structure differs, following contrast and detail more than plain differences in
+    /// value do.
+    pub fn ssim<Q: AsRef<[Pixel]>>(&self, other: &Image<Q>) -> Result<f64, Error> {
+        if self.dimensions != other.dimensions {
+            return Err(Error::InvalidDimensions);
+        }
+        let (width, height) = self.dimensions;
+        let window = SSIM_WINDOW.min(width).min(height);
+        if window == 0 {
+            return Err(Error::InvalidDimensions);
+        }
+        let step = (window / 2).max(1);
+
+        let mut total = 0.0;
+        let mut windows = 0;
+        for top in (0..=height - window).step_by(step as usize) {
+            for left in (0..=width - window).step_by(step as usize) {
+                // Running sums over the window, so that no window needs its values collected.
+                let (mut sum_a, mut sum_b) = (0.0, 0.0);
+                let (mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0);
+                for y in top..top + window {
+                    let row = (y * width) as usize;
+                    for x in left..left + window {
+                        let i = row + x as usize;
+                        let (a, b) = (luma(self.pixels()[i]), luma(other.pixels()[i]));
+                        sum_a += a;
+                        sum_b += b;
+                        sum_aa += a * a;
+                        sum_bb += b * b;
+                        sum_ab += a * b;
+                    }
+                }
+                let count = (window * window) as f64;
+                let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+                let variance_a = sum_aa / count - mean_a * mean_a;
+                let variance_b = sum_bb / count - mean_b * mean_b;
+                let covariance = sum_ab / count - mean_a * mean_b;
+
+                total += (2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2)
+                    / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1)
+                        * (variance_a + variance_b + SSIM_C2));
+                windows += 1;
+            }
+        }
+        Ok(total / windows as f64)
+    }
+}
@@ -0,0 +1,272 @@
+//! Systematic Reed-Solomon coding over GF(2^8).
+//!
+//! This uses the 0x11d field polynomial and a first consecutive root of 0, the same parameters as
+//! QR codes.  Polynomials are stored highest-degree coefficient first, so a codeword is simply the
+//! message bytes followed by the parity bytes.  A codeword can be at most 255 bytes long.
+
+use super::Error;
+
+const PRIMITIVE: u16 = 0x11d;
+
+struct Tables {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+const fn build_tables() -> Tables {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= PRIMITIVE;
+        }
+        i += 1;
+    }
+    // Doubled so that multiplication never needs a modulo.
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    Tables { exp, log }
+}
+
+const TABLES: Tables = build_tables();
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        0
+    } else {
+        TABLES.exp[TABLES.log[a as usize] as usize + TABLES.log[b as usize] as usize]
+    }
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        0
+    } else {
+        TABLES.exp[(TABLES.log[a as usize] as usize + 255 - TABLES.log[b as usize] as usize) % 255]
+    }
+}
+
+fn inverse(a: u8) -> u8 {
+    TABLES.exp[255 - TABLES.log[a as usize] as usize]
+}
+
+/// Raise the generator to the given power.
+fn alpha(power: usize) -> u8 {
+    TABLES.exp[power % 255]
+}
+
+fn poly_scale(poly: &[u8], factor: u8) -> Vec<u8> {
+    poly.iter()
+        .map(|&coefficient| mul(coefficient, factor))
+        .collect()
+}
+
+/// Add two polynomials, aligning them on their lowest-degree coefficient.
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let length = p.len().max(q.len());
+    let mut output = vec![0; length];
+    for (i, &coefficient) in p.iter().enumerate() {
+        output[i + length - p.len()] = coefficient;
+    }
+    for (i, &coefficient) in q.iter().enumerate() {
+        output[i + length - q.len()] ^= coefficient;
+    }
+    output
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut output = vec![0; p.len() + q.len() - 1];
+    for (j, &q_coefficient) in q.iter().enumerate() {
+        for (i, &p_coefficient) in p.iter().enumerate() {
+            output[i + j] ^= mul(p_coefficient, q_coefficient);
+        }
+    }
+    output
+}
+
+fn poly_eval(poly: &[u8], x: u8) -> u8 {
+    poly.iter()
+        .skip(1)
+        .fold(poly[0], |acc, &coefficient| mul(acc, x) ^ coefficient)
+}
+
+fn generator(parity: usize) -> Vec<u8> {
+    (0..parity).fold(vec![1], |generator, i| poly_mul(&generator, &[1, alpha(i)]))
+}
+
+/// Compute the parity bytes for the given message.
+pub fn encode(message: &[u8], parity: usize) -> Vec<u8> {
+    debug_assert!(message.len() + parity <= 255);
+    let generator = generator(parity);
+    let mut output = message.to_vec();
+    output.resize(message.len() + parity, 0);
+
+    for i in 0..message.len() {
+        let coefficient = output[i];
+        if coefficient != 0 {
+            for (j, &generator_coefficient) in generator.iter().enumerate().skip(1) {
+                output[i + j] ^= mul(generator_coefficient, coefficient);
+            }
+        }
+    }
+
+    output.split_off(message.len())
+}
+
+/// Syndromes of the codeword, with a leading zero so that indexing lines up with the
+/// Berlekamp-Massey iteration.
+fn syndromes(codeword: &[u8], parity: usize) -> Vec<u8> {
+    std::iter::once(0)
+        .chain((0..parity).map(|i| poly_eval(codeword, alpha(i))))
+        .collect()
+}
+
+/// Berlekamp-Massey.  The returned locator is highest-degree first.
+fn error_locator(syndromes: &[u8], parity: usize) -> Result<Vec<u8>, Error> {
+    let mut locator = vec![1];
+    let mut old_locator = vec![1];
+
+    for k in 1..=parity {
+        let mut delta = syndromes[k];
+        for j in 1..locator.len() {
+            delta ^= mul(locator[locator.len() - 1 - j], syndromes[k - j]);
+        }
+        old_locator.push(0);
+        if delta != 0 {
+            if old_locator.len() > locator.len() {
+                let new_locator = poly_scale(&old_locator, delta);
+                old_locator = poly_scale(&locator, inverse(delta));
+                locator = new_locator;
+            }
+            locator = poly_add(&locator, &poly_scale(&old_locator, delta));
+        }
+    }
+
+    let leading_zeros = locator.iter().take_while(|&&c| c == 0).count();
+    locator.drain(..leading_zeros);
+
+    if (locator.len() - 1) * 2 > parity {
+        Err(Error::TooManyErrors)
+    } else {
+        Ok(locator)
+    }
+}
+
+/// Chien search, returning the error positions as indices into the codeword.
+fn error_positions(locator: &[u8], length: usize) -> Result<Vec<usize>, Error> {
+    let reversed: Vec<u8> = locator.iter().rev().copied().collect();
+    let positions: Vec<usize> = (0..length)
+        .filter(|&i| poly_eval(&reversed, alpha(i)) == 0)
+        .map(|i| length - 1 - i)
+        .collect();
+
+    if positions.len() != locator.len() - 1 {
+        Err(Error::TooManyErrors)
+    } else {
+        Ok(positions)
+    }
+}
+
+/// Forney algorithm, fixing the codeword in place.
+fn correct_errata(codeword: &mut [u8], syndromes: &[u8], positions: &[usize]) -> Result<(), Error> {
+    let coefficient_positions: Vec<usize> = positions
+        .iter()
+        .map(|&position| codeword.len() - 1 - position)
+        .collect();
+
+    let locator = coefficient_positions
+        .iter()
+        .fold(vec![1], |locator, &position| {
+            poly_mul(&locator, &[alpha(position), 1])
+        });
+
+    // Error evaluator: (syndromes * locator) mod x^(errors + 1)
+    let reversed_syndromes: Vec<u8> = syndromes.iter().rev().copied().collect();
+    let product = poly_mul(&reversed_syndromes, &locator);
+    let evaluator = &product[product.len().saturating_sub(locator.len())..];
+
+    let roots: Vec<u8> = coefficient_positions
+        .iter()
+        .map(|&position| alpha(position))
+        .collect();
+
+    for (i, &root) in roots.iter().enumerate() {
+        let root_inverse = inverse(root);
+        let locator_derivative = roots
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |acc, (_, &other)| mul(acc, 1 ^ mul(root_inverse, other)));
+        if locator_derivative == 0 {
+            return Err(Error::TooManyErrors);
+        }
+        let y = mul(root, poly_eval(evaluator, root_inverse));
+        codeword[positions[i]] ^= div(y, locator_derivative);
+    }
+    Ok(())
+}
+
+/// Correct a codeword in place, returning the indices of the bytes that were repaired.
+pub fn correct(codeword: &mut [u8], parity: usize) -> Result<Vec<usize>, Error> {
+    let syndromes = syndromes(codeword, parity);
+    if syndromes.iter().all(|&s| s == 0) {
+        return Ok(Vec::new());
+    }
+
+    let locator = error_locator(&syndromes, parity)?;
+    let positions = error_positions(&locator, codeword.len())?;
+    correct_errata(codeword, &syndromes, &positions)?;
+
+    if self::syndromes(codeword, parity).iter().any(|&s| s != 0) {
+        Err(Error::TooManyErrors)
+    } else {
+        Ok(positions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::seq::index::sample;
+    use rand::Rng;
+
+    #[test]
+    fn corrects_up_to_half_parity() {
+        let mut rng = rand::thread_rng();
+        for &(length, parity) in &[(10, 4), (100, 32), (223, 32), (102, 153)] {
+            let message: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+            let mut codeword = message.clone();
+            codeword.extend(encode(&message, parity));
+
+            let mut damaged = codeword.clone();
+            let mut positions = sample(&mut rng, damaged.len(), parity / 2).into_vec();
+            for &position in &positions {
+                damaged[position] ^= rng.gen_range(1, 256) as u8;
+            }
+
+            let mut corrected = correct(&mut damaged, parity).expect("Could not correct");
+            assert_eq!(codeword, damaged);
+            corrected.sort();
+            positions.sort();
+            assert_eq!(corrected, positions);
+        }
+    }
+
+    #[test]
+    fn rejects_too_many_errors() {
+        let message: Vec<u8> = (0..50).collect();
+        let mut codeword = message.clone();
+        codeword.extend(encode(&message, 4));
+        for byte in &mut codeword[..10] {
+            *byte ^= 0x55;
+        }
+        assert!(correct(&mut codeword, 4).is_err());
+    }
+}
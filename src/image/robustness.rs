@@ -0,0 +1,188 @@
+//! How well baked payloads survive lossy recompression.
+//!
+//! Every fixture in `art/` has a payload baked into it, and is then scaled and run through JPEG
+//! at a range of qualities.  The full table of decode success rates is printed by
+//! `cargo test --release --lib robustness -- --ignored --nocapture`.
+
+use super::{Baking, Density, Encoding, Error, Image, Payload, Pixel};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+
+const FIXTURES: [&str; 4] = [
+    concat!(env!("CARGO_MANIFEST_DIR"), "/art/blank.png"),
+    concat!(env!("CARGO_MANIFEST_DIR"), "/art/starrynight.jpg"),
+    concat!(env!("CARGO_MANIFEST_DIR"), "/art/thelastsupper.jpg"),
+    concat!(env!("CARGO_MANIFEST_DIR"), "/art/thescream.jpg"),
+];
+
+/// Roughly the size of a short compressed song.
+const PAYLOAD_LEN: usize = 200;
+
+struct Mode {
+    name: &'static str,
+    density: Density,
+    baking: Baking,
+}
+
+fn modes() -> Vec<Mode> {
+    vec![
+        Mode {
+            name: "standard",
+            density: Density::Standard,
+            baking: Baking::default(),
+        },
+        Mode {
+            name: "robust",
+            density: Density::Robust,
+            baking: Baking::default(),
+        },
+        Mode {
+            name: "jpeg standard",
+            density: Density::Standard,
+            baking: Baking::jpeg(),
+        },
+        Mode {
+            name: "jpeg robust",
+            density: Density::Robust,
+            baking: Baking::jpeg(),
+        },
+    ]
+}
+
+fn data() -> Vec<u8> {
+    (0..PAYLOAD_LEN).map(|i| (i * 151 % 256) as u8).collect()
+}
+
+fn to_image(buffer: &RgbaImage) -> Image {
+    let pixels: Vec<Pixel> = buffer
+        .pixels()
+        .map(|pixel| Pixel {
+            r: pixel[0],
+            g: pixel[1],
+            b: pixel[2],
+            a: pixel[3],
+        })
+        .collect();
+    Image::new(buffer.dimensions(), pixels)
+}
+
+fn to_buffer(image: &Image) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let mut buffer = RgbaImage::new(width, height);
+    for (out_pixel, pixel) in buffer.pixels_mut().zip(image.pixels()) {
+        *out_pixel = image::Rgba([pixel.r, pixel.g, pixel.b, pixel.a]);
+    }
+    buffer
+}
+
+/// Bake the payload into a fixture with the given mode, if it fits.
+fn bake(fixture: &str, mode: &Mode) -> Option<RgbaImage> {
+    let payload = Payload::with_encoding(
+        data(),
+        Encoding {
+            density: mode.density,
+            ..Encoding::default()
+        },
+    )
+    .expect("Could not create payload");
+    let mut image = to_image(
+        &image::open(fixture)
+            .expect("Could not open fixture")
+            .into_rgba(),
+    );
+    match image.bake_payload_with(&payload, mode.baking) {
+        Ok(()) => Some(to_buffer(&image)),
+        Err(Error::ImageTooSmall) => None,
+        Err(error) => panic!("Could not bake payload: {}", error),
+    }
+}
+
+/// Scale a baked image, then compress it as a JPEG of the given quality and load it back.
+fn recompress(baked: &RgbaImage, scale: f32, quality: u8) -> Image {
+    let (width, height) = baked.dimensions();
+    let scaled = DynamicImage::ImageRgba8(baked.clone()).resize_exact(
+        (width as f32 * scale).round() as u32,
+        (height as f32 * scale).round() as u32,
+        FilterType::Triangle,
+    );
+    let rgb = scaled.to_rgb();
+    let mut encoded = Vec::new();
+    image::jpeg::JPEGEncoder::new_with_quality(&mut encoded, quality)
+        .encode(&rgb, rgb.width(), rgb.height(), image::ColorType::Rgb8)
+        .expect("Could not encode jpeg");
+    to_image(
+        &image::load_from_memory_with_format(&encoded, ImageFormat::Jpeg)
+            .expect("Could not decode jpeg")
+            .into_rgba(),
+    )
+}
+
+fn decodes(image: &Image) -> bool {
+    image
+        .read_payload()
+        .and_then(|payload| payload.data())
+        .map(|read| read == data())
+        .unwrap_or(false)
+}
+
+#[test]
+fn jpeg_mode_survives_quality_75() {
+    let mode = Mode {
+        name: "jpeg standard",
+        density: Density::Standard,
+        baking: Baking::jpeg(),
+    };
+    // Superpixels of whole blocks need bigger images, so small fixtures are skipped.
+    let baked: Vec<(&str, RgbaImage)> = FIXTURES
+        .iter()
+        .filter_map(|fixture| Some((*fixture, bake(fixture, &mode)?)))
+        .collect();
+    assert!(baked.len() >= 2, "Too few fixtures hold the payload");
+    for (fixture, baked) in &baked {
+        assert!(
+            decodes(&recompress(baked, 1.0, 75)),
+            "{} did not survive quality 75 in {} mode",
+            fixture,
+            mode.name
+        );
+    }
+}
+
+#[test]
+#[ignore]
+fn recompression_success_rates() {
+    let qualities = [95, 85, 75, 60, 50, 30];
+    let scales = [1.0, 0.75, 0.5];
+
+    println!(
+        "{:<16} {:>4} {:>6} {}",
+        "mode",
+        "fit",
+        "scale",
+        qualities
+            .iter()
+            .map(|quality| format!("{:>6}", format!("q{}", quality)))
+            .collect::<String>()
+    );
+    for mode in &modes() {
+        // Rates are out of the fixtures big enough to hold the payload in this mode.
+        let baked: Vec<RgbaImage> = FIXTURES
+            .iter()
+            .filter_map(|fixture| bake(fixture, mode))
+            .collect();
+        let fit = format!("{}/{}", baked.len(), FIXTURES.len());
+        for &scale in &scales {
+            let rates: String = qualities
+                .iter()
+                .map(|&quality| {
+                    let successes = baked
+                        .iter()
+                        .filter(|baked| decodes(&recompress(baked, scale, quality)))
+                        .count();
+                    format!("{:>5}%", successes * 100 / baked.len().max(1))
+                })
+                .collect();
+            println!("{:<16} {:>4} {:>6} {}", mode.name, fit, scale, rates);
+        }
+    }
+}
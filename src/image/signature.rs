@@ -0,0 +1,43 @@
+use super::Error;
+use std::convert::TryInto;
+
+/// Length of an Ed25519 public key.
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Length of an Ed25519 signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Who signed a payload, and their signature over its data and the header fields that say how to
+/// read it.  This is carried in front of the data when the header is flagged as signed.  The image
+/// format only carries these bytes; it's up to the reader to check that the signature holds.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct Signature {
+    pub public_key: [u8; PUBLIC_KEY_LEN],
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl Signature {
+    pub(crate) const LEN: usize = PUBLIC_KEY_LEN + SIGNATURE_LEN;
+
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.public_key.to_vec();
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    /// Split the signature off of the front of signed data.
+    pub(crate) fn split(data: &[u8]) -> Result<(Signature, &[u8]), Error> {
+        if data.len() < Signature::LEN {
+            return Err(Error::MissingSignature);
+        }
+        let (public_key, rest) = data.split_at(PUBLIC_KEY_LEN);
+        let (signature, data) = rest.split_at(SIGNATURE_LEN);
+        Ok((
+            Signature {
+                public_key: public_key.try_into().unwrap(),
+                signature: signature.try_into().unwrap(),
+            },
+            data,
+        ))
+    }
+}
@@ -4,17 +4,21 @@
  * The main entry point to this crate is [`song::Song`](song/struct.Song.html)
  */
 
+pub mod encryption;
 pub mod envelope;
 pub mod image;
 pub mod instrument;
 pub mod musicxml;
 pub mod note;
+pub mod signing;
 pub mod song;
 pub mod voice;
 
 pub use crate::song::Song;
 
-use crate::image::{Image, Payload, Pixel};
+use crate::image::{
+    Baking, Codec, Compression, Decoded, Encoding, Flags, Header, Image, Payload, Pixel, Plan,
+};
 use flate2::read::GzDecoder;
 use flate2::read::GzEncoder;
 use minidom::Element;
@@ -92,33 +96,151 @@ pub fn song_from_image(
     image_height: u32,
     image_data: Clamped<Vec<u8>>,
 ) -> Result<*mut Song, JsValue> {
-    let image_data: Vec<Pixel> = image_data
-        .chunks_exact(4)
-        .map(|chunk| Pixel {
-            r: chunk[0],
-            g: chunk[1],
-            b: chunk[2],
-            a: chunk[3],
-        })
-        .collect();
-
-    let image = Image::new((image_width, image_height), image_data);
-
-    let payload = image
-        .read_payload()
-        .map_err(|e| JsValue::from(e.to_string()))?;
-    let payload = payload.data().map_err(|e| JsValue::from(e.to_string()))?;
-
-    let mut decoder = GzDecoder::new(&payload[..]);
-    let mut buffer = Vec::new();
-    decoder
-        .read_to_end(&mut buffer)
-        .map_err(|e| JsValue::from(e.to_string()))?;
+    song_from_image_with_passphrase(image_width, image_height, image_data, None)
+}
 
-    let song = bincode::deserialize(&buffer).map_err(|e| JsValue::from(e.to_string()))?;
+/// Read a song out of an image that may have been encrypted with a passphrase
+#[wasm_bindgen]
+pub fn song_from_image_with_passphrase(
+    image_width: u32,
+    image_height: u32,
+    image_data: Clamped<Vec<u8>>,
+    passphrase: Option<String>,
+) -> Result<*mut Song, JsValue> {
+    let song = song_from_rgba(
+        image_width,
+        image_height,
+        &image_data,
+        passphrase.as_deref(),
+    )
+    .map_err(|e| JsValue::from(e.to_string()))?;
     Ok(Box::into_raw(Box::new(song)))
 }
 
+/// Read a song out of RGBA image data, four bytes to a pixel in row-major order.  Every
+/// malformed image, including data that doesn't match the dimensions, is an error rather than a
+/// panic, so this is safe to run on images from anywhere.
+pub fn song_from_rgba(
+    image_width: u32,
+    image_height: u32,
+    image_data: &[u8],
+    passphrase: Option<&str>,
+) -> Result<Song, Box<dyn std::error::Error>> {
+    let pixels = Pixel::from_rgba(image_data).ok_or(image::Error::InvalidDimensions)?;
+    let image = Image::from_buffer((image_width, image_height), pixels)?;
+    let payload = image.read_payload()?;
+    song_from_payload_with_passphrase(&payload, passphrase)
+}
+
+/// Decode a song out of a payload read from an image.
+pub fn song_from_payload(payload: &Payload) -> Result<Song, Box<dyn std::error::Error>> {
+    song_from_payload_with_passphrase(payload, None)
+}
+
+/// Decode a song out of a payload read from an image, decrypting it with the passphrase if it
+/// was encrypted.  Encrypted payloads without a passphrase fail with
+/// [`encryption::Error::NeedsPassphrase`], and with the wrong one with
+/// [`encryption::Error::WrongPassphrase`].
+pub fn song_from_payload_with_passphrase(
+    payload: &Payload,
+    passphrase: Option<&str>,
+) -> Result<Song, Box<dyn std::error::Error>> {
+    unpack_payload(payload, passphrase).map(|unpacked| unpacked.song)
+}
+
+/// The most a song may decompress to.  Real songs are far smaller, so anything larger is taken to
+/// be a decompression bomb.
+const MAX_SONG_LEN: u64 = 16 * 1024 * 1024;
+
+/// A song read out of a payload, along with who signed it.
+pub struct Unpacked {
+    pub song: Song,
+
+    /// The key the song was signed with, if it was signed.  The signature has been checked.
+    pub signer: Option<signing::PublicKey>,
+}
+
+/// Decode a song out of a payload read from an image, as with
+/// [`song_from_payload_with_passphrase`], also checking its signature if it was signed.  Songs
+/// whose signature doesn't hold fail with [`signing::Error::BadSignature`].
+pub fn unpack_payload(
+    payload: &Payload,
+    passphrase: Option<&str>,
+) -> Result<Unpacked, Box<dyn std::error::Error>> {
+    unpack_payloads(std::slice::from_ref(payload), passphrase)
+}
+
+/// Decode a song spread over the payloads of several frames, as with [`unpack_payload`].  The
+/// frames may be in any order, but every one of them has to be there.
+pub fn unpack_payloads(
+    payloads: &[Payload],
+    passphrase: Option<&str>,
+) -> Result<Unpacked, Box<dyn std::error::Error>> {
+    let payload = Decoded::join(
+        payloads
+            .iter()
+            .map(Payload::decode)
+            .collect::<Result<Vec<_>, _>>()?,
+    )?;
+
+    let signer = match &payload.signature {
+        Some(signature) => Some(signing::verify(&payload.header, &payload.data, signature)?),
+        None => None,
+    };
+
+    let data = if payload.header.flags.contains(Flags::ENCRYPTED) {
+        let passphrase = passphrase.ok_or(encryption::Error::NeedsPassphrase)?;
+        encryption::decrypt(&payload.data, passphrase)?
+    } else {
+        payload.data
+    };
+
+    let buffer = match payload.header.compression {
+        Compression::None => data,
+        Compression::Gzip => {
+            // Read one byte past the limit, to tell a song that just fits from one that doesn't.
+            let mut decoder = GzDecoder::new(&data[..]).take(MAX_SONG_LEN + 1);
+            let mut buffer = Vec::new();
+            decoder.read_to_end(&mut buffer)?;
+            if buffer.len() as u64 > MAX_SONG_LEN {
+                return Err("Song is too large to decompress".into());
+            }
+            buffer
+        }
+    };
+
+    let song = match payload.header.codec {
+        Codec::Bincode => bincode::deserialize(&buffer)?,
+        codec => return Err(format!("Image does not contain a song, but {:?} data", codec).into()),
+    };
+    Ok(Unpacked { song, signer })
+}
+
+/// Serialize and compress a song, the way it is baked into images.
+pub fn compress_song(song: &Song) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let bincode = bincode::serialize(song)?;
+    let mut compressed = Vec::new();
+    let mut compressor = GzEncoder::new(bincode.as_slice(), flate2::Compression::best());
+    compressor.read_to_end(&mut compressed)?;
+    Ok(compressed)
+}
+
+/// How a song would come out when baked into an image of these dimensions.  Encryption is planned
+/// for when the header is flagged as encrypted, and signing when the encoding has a signature,
+/// whose contents don't matter.
+pub fn plan_song(
+    song: &Song,
+    dimensions: (u32, u32),
+    encoding: Encoding,
+    baking: Baking,
+) -> Result<Plan, Box<dyn std::error::Error>> {
+    let mut len = compress_song(song)?.len();
+    if encoding.header.flags.contains(Flags::ENCRYPTED) {
+        len += encryption::OVERHEAD;
+    }
+    Ok(Payload::plan(dimensions, len, encoding, baking)?)
+}
+
 /// Bake a song into an image.
 ///
 /// Dimensions aren't returned because they are the same as the input ones, so the caller already
@@ -129,39 +251,55 @@ pub fn song_bake_image(
     image_width: u32,
     image_height: u32,
     image_data: Clamped<Vec<u8>>,
+) -> Result<Vec<u8>, JsValue> {
+    song_bake_image_with_passphrase(song, image_width, image_height, image_data, None)
+}
+
+/// Bake a song into an image, encrypting it with the passphrase if one is given.  The image data
+/// is baked in place and handed back, rather than copied.
+#[wasm_bindgen]
+pub fn song_bake_image_with_passphrase(
+    song: *mut Song,
+    image_width: u32,
+    image_height: u32,
+    image_data: Clamped<Vec<u8>>,
+    passphrase: Option<String>,
 ) -> Result<Vec<u8>, JsValue> {
     let song = unsafe { &mut *song };
 
-    let bincode = bincode::serialize(&song).map_err(|e| JsValue::from(e.to_string()))?;
-    let mut compressed = Vec::new();
-    {
-        let mut compressor = GzEncoder::new(bincode.as_slice(), flate2::Compression::best());
-        compressor
-            .read_to_end(&mut compressed)
+    let mut compressed = compress_song(song).map_err(|e| JsValue::from(e.to_string()))?;
+
+    let mut header = Header {
+        compression: Compression::Gzip,
+        codec: Codec::Bincode,
+        ..Header::default()
+    };
+    if let Some(passphrase) = passphrase {
+        compressed = encryption::encrypt(&compressed, &passphrase)
             .map_err(|e| JsValue::from(e.to_string()))?;
+        header.flags.insert(Flags::ENCRYPTED);
     }
 
-    let payload = Payload::new(&compressed);
-
-    let image_data: Vec<Pixel> = image_data
-        .chunks_exact(4)
-        .map(|chunk| Pixel {
-            r: chunk[0],
-            g: chunk[1],
-            b: chunk[2],
-            a: chunk[3],
-        })
-        .collect();
-
-    let mut image = Image::new((image_width, image_height), image_data);
+    let aspect = Baking::default()
+        .grid_size((image_width, image_height))
+        .map_err(|e| JsValue::from(e.to_string()))?;
+    let encoding = Encoding {
+        header,
+        aspect: Some(aspect),
+        ..Encoding::default()
+    };
+    let payload =
+        Payload::with_encoding(&compressed, encoding).map_err(|e| JsValue::from(e.to_string()))?;
 
-    image.bake_payload(&payload);
+    let Clamped(mut image_data) = image_data;
+    let pixels = Pixel::from_rgba_mut(&mut image_data)
+        .ok_or_else(|| JsValue::from(image::Error::InvalidDimensions.to_string()))?;
+    let mut image = Image::from_buffer((image_width, image_height), pixels)
+        .map_err(|e| JsValue::from(e.to_string()))?;
 
-    let image_data: Vec<u8> = image
-        .pixels()
-        .iter()
-        .flat_map(|pixel| vec![pixel.r, pixel.g, pixel.b, pixel.a])
-        .collect();
+    image
+        .bake_payload(&payload)
+        .map_err(|e| JsValue::from(e.to_string()))?;
 
     Ok(image_data)
 }
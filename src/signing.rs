@@ -0,0 +1,159 @@
+//! Signing of song data, so that readers can tell who baked an image.
+//!
+//! Songs are signed with Ed25519 over the data as it is stored in the payload, after compression
+//! and any encryption, so a signature can be checked without knowing the passphrase.  The header
+//! fields that say how to read that data back are signed along with it, so that they can't be
+//! changed without breaking the signature.  Keys are kept as hex, with a signing key stored as its
+//! 32-byte seed.
+
+use crate::image::{Flags, Header, Signature, PUBLIC_KEY_LEN};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum Error {
+    /// The signature doesn't match the data and the key it claims to be from.
+    BadSignature,
+    /// A key isn't valid hex of the right length, or isn't a valid Ed25519 key.
+    InvalidKey,
+    /// No random key could be drawn.
+    Random,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// What a signature is over: the compression, codec, and whether the data is encrypted, followed
+/// by the data itself.  The rest of the header only says how the data is laid out in an image,
+/// which doesn't change what it reads back as.
+fn signed_message(header: &Header, data: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(3 + data.len());
+    message.push(header.compression.into());
+    message.push(header.codec.into());
+    message.push(header.flags.contains(Flags::ENCRYPTED) as u8);
+    message.extend_from_slice(data);
+    message
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex<const N: usize>(hex: &str) -> Result<[u8; N], Error> {
+    let hex = hex.trim();
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return Err(Error::InvalidKey);
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| Error::InvalidKey)?;
+    }
+    Ok(bytes)
+}
+
+/// The public half of a signing key, which identifies who signed a song.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub struct PublicKey(pub [u8; PUBLIC_KEY_LEN]);
+
+impl PublicKey {
+    pub fn from_hex(hex: &str) -> Result<Self, Error> {
+        from_hex(hex).map(PublicKey)
+    }
+}
+
+impl fmt::Display for PublicKey {
+    /// The key as hex.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&to_hex(&self.0))
+    }
+}
+
+/// A key to sign songs with.
+pub struct SecretKey(SigningKey);
+
+impl SecretKey {
+    /// Draw a new random key.
+    pub fn generate() -> Result<Self, Error> {
+        let mut seed = [0; 32];
+        getrandom::getrandom(&mut seed).map_err(|_| Error::Random)?;
+        Ok(SecretKey(SigningKey::from_bytes(&seed)))
+    }
+
+    /// Read a key from the hex of its seed, as written by [`SecretKey::to_hex`].
+    pub fn from_hex(hex: &str) -> Result<Self, Error> {
+        from_hex(hex).map(|seed| SecretKey(SigningKey::from_bytes(&seed)))
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(self.0.as_bytes())
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.0.verifying_key().to_bytes())
+    }
+
+    /// Sign data along with the header it is baked under, for baking alongside it.
+    pub fn sign(&self, header: &Header, data: &[u8]) -> Signature {
+        Signature {
+            public_key: self.public_key().0,
+            signature: self.0.sign(&signed_message(header, data)).to_bytes(),
+        }
+    }
+}
+
+/// Check that a signature holds over data and the header it was read with, returning who signed
+/// it.
+pub fn verify(header: &Header, data: &[u8], signature: &Signature) -> Result<PublicKey, Error> {
+    let key = VerifyingKey::from_bytes(&signature.public_key).map_err(|_| Error::InvalidKey)?;
+    key.verify_strict(&signed_message(header, data), &signature.signature.into())
+        .map_err(|_| Error::BadSignature)?;
+    Ok(PublicKey(signature.public_key))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image::Compression;
+
+    #[test]
+    fn sign_and_verify() {
+        let key = SecretKey::generate().expect("Could not generate key");
+        let key = SecretKey::from_hex(&key.to_hex()).expect("Could not read key");
+        let header = Header::default();
+        let data = b"a contest entry";
+        let signature = key.sign(&header, data);
+        assert_eq!(verify(&header, data, &signature), Ok(key.public_key()));
+        assert_eq!(
+            PublicKey::from_hex(&key.public_key().to_string()),
+            Ok(key.public_key())
+        );
+
+        assert_eq!(
+            verify(&header, b"a forged entry", &signature),
+            Err(Error::BadSignature)
+        );
+
+        // Changing how the data is read back breaks the signature too.
+        let mut encrypted = header;
+        encrypted.flags.insert(Flags::ENCRYPTED);
+        let recompressed = Header {
+            compression: Compression::Gzip,
+            ..header
+        };
+        for forged in &[encrypted, recompressed] {
+            assert_eq!(verify(forged, data, &signature), Err(Error::BadSignature));
+        }
+        let other = SecretKey::generate().expect("Could not generate key");
+        let forged = Signature {
+            public_key: other.public_key().0,
+            ..signature
+        };
+        assert_eq!(verify(&header, data, &forged), Err(Error::BadSignature));
+        assert_eq!(PublicKey::from_hex("abc"), Err(Error::InvalidKey));
+    }
+}